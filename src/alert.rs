@@ -0,0 +1,330 @@
+// Copyright (C) 2026 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Price alerts and the logic for deciding when they fire.
+
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::fs::create_dir_all;
+use std::fs::read_to_string;
+use std::fs::write;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Context as _;
+use anyhow::Result;
+
+use num_decimal::Num;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+
+/// The condition under which an alert fires.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+  /// The price is at or above the given value.
+  Above(Num),
+  /// The price is at or below the given value.
+  Below(Num),
+  /// The price changed by at least the given percentage relative to
+  /// the previous day's close (downwards, if negative).
+  Change(Num),
+}
+
+impl Condition {
+  /// Check whether the condition holds for the given price and the
+  /// previous day's close (if known).
+  pub fn holds(&self, price: &Num, close: Option<&Num>) -> bool {
+    match self {
+      Self::Above(value) => price >= value,
+      Self::Below(value) => price <= value,
+      Self::Change(percent) => match close {
+        Some(close) if !close.is_zero() => {
+          let change = (price / close - 1) * 100;
+          if percent.is_negative() {
+            &change <= percent
+          } else {
+            &change >= percent
+          }
+        },
+        _ => false,
+      },
+    }
+  }
+
+  /// Check whether the condition needs the previous day's close to be
+  /// evaluated.
+  pub fn needs_close(&self) -> bool {
+    matches!(self, Self::Change(..))
+  }
+}
+
+impl Display for Condition {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    match self {
+      Self::Above(value) => write!(fmt, "above {value}"),
+      Self::Below(value) => write!(fmt, "below {value}"),
+      Self::Change(percent) => write!(fmt, "change {percent}%"),
+    }
+  }
+}
+
+
+/// A price alert.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Alert {
+  /// The ID identifying the alert.
+  pub id: u64,
+  /// The symbol whose price to watch.
+  pub symbol: String,
+  /// The condition under which the alert fires.
+  pub condition: Condition,
+  /// The shell command to run when the alert fires.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub command: Option<String>,
+  /// Whether the alert re-arms once the condition no longer holds,
+  /// instead of firing only once.
+  #[serde(default)]
+  pub rearm: bool,
+}
+
+
+/// The set of alerts, as stored on disk.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Alerts {
+  /// The ID to assign to the next alert added.
+  ///
+  /// IDs are never reused, so that an ID always refers to the same
+  /// alert, even if it got removed in the meantime.
+  #[serde(default)]
+  next_id: u64,
+  #[serde(default, rename = "alert")]
+  pub alerts: Vec<Alert>,
+}
+
+impl Alerts {
+  /// Load alerts from the file at the given path, treating a missing
+  /// file as containing no alerts.
+  pub fn load(path: &Path) -> Result<Self> {
+    let toml = match read_to_string(path) {
+      Ok(toml) => toml,
+      Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+      Err(err) => {
+        return Err(err).with_context(|| format!("failed to read alerts file {}", path.display()))
+      },
+    };
+    toml::from_str::<Self>(&toml)
+      .with_context(|| format!("failed to parse alerts file {}", path.display()))
+  }
+
+  /// Save alerts to the file at the given path.
+  pub fn save(&self, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+      let () = create_dir_all(dir)
+        .with_context(|| format!("failed to create directory {}", dir.display()))?;
+    }
+    let toml = toml::to_string(self).context("failed to serialize alerts")?;
+    write(path, toml).with_context(|| format!("failed to write alerts file {}", path.display()))
+  }
+
+  /// Add an alert, assigning it an ID that was never used before.
+  pub fn add(
+    &mut self,
+    symbol: String,
+    condition: Condition,
+    command: Option<String>,
+    rearm: bool,
+  ) -> &Alert {
+    let max = self.alerts.iter().map(|alert| alert.id).max().unwrap_or(0);
+    let id = self.next_id.max(max + 1);
+    self.next_id = id + 1;
+    let alert = Alert {
+      id,
+      symbol,
+      condition,
+      command,
+      rearm,
+    };
+    self.alerts.push(alert);
+    // SANITY: We just pushed an element.
+    self.alerts.last().unwrap()
+  }
+
+  /// Remove the alert with the given ID, returning whether it existed.
+  pub fn remove(&mut self, id: u64) -> bool {
+    let count = self.alerts.len();
+    self.alerts.retain(|alert| alert.id != id);
+    self.alerts.len() != count
+  }
+}
+
+
+/// The state of an alert being watched.
+#[derive(Debug)]
+pub struct Watch {
+  /// The alert.
+  pub alert: Alert,
+  /// Whether the alert can fire.
+  armed: bool,
+  /// Whether the alert has fired.
+  fired: bool,
+}
+
+impl Watch {
+  /// Start watching an alert.
+  ///
+  /// The alert starts out disarmed, so that it only fires once the
+  /// price actually crosses its threshold.
+  pub fn new(alert: Alert) -> Self {
+    Self {
+      alert,
+      armed: false,
+      fired: false,
+    }
+  }
+
+  /// Evaluate the alert for a new price, returning whether it fires.
+  ///
+  /// An alert is armed once its condition does not hold and fires when
+  /// the condition holds while it is armed. It then is disarmed and,
+  /// if it re-arms, armed again once the condition ceases to hold.
+  pub fn update(&mut self, price: &Num, close: Option<&Num>) -> bool {
+    let holds = self.alert.condition.holds(price, close);
+    if self.armed && holds {
+      self.armed = false;
+      self.fired = true;
+      true
+    } else {
+      if !self.armed && !holds && (!self.fired || self.alert.rearm) {
+        self.armed = true;
+      }
+      false
+    }
+  }
+
+  /// Check whether the alert is done, i.e., will never fire again.
+  pub fn is_done(&self) -> bool {
+    self.fired && !self.alert.rearm
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use tempfile::tempdir;
+
+
+  /// Check that alerts can be saved and loaded again.
+  #[test]
+  fn save_and_load_alerts() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("apcacli").join("alerts.toml");
+
+    let alerts = Alerts::load(&path).unwrap();
+    assert_eq!(alerts, Alerts::default());
+
+    let mut alerts = Alerts::default();
+    let alert = alerts.add(
+      "AAPL".to_string(),
+      Condition::Above(Num::from(200)),
+      Some("notify-send AAPL".to_string()),
+      false,
+    );
+    assert_eq!(alert.id, 1);
+    let alert = alerts.add(
+      "SPY".to_string(),
+      Condition::Change(Num::new(-25, 10)),
+      None,
+      true,
+    );
+    assert_eq!(alert.id, 2);
+    let () = alerts.save(&path).unwrap();
+
+    let loaded = Alerts::load(&path).unwrap();
+    assert_eq!(loaded, alerts);
+
+    // IDs of removed alerts are not reused, not even after a reload.
+    assert!(alerts.remove(2));
+    assert!(!alerts.remove(2));
+    let () = alerts.save(&path).unwrap();
+
+    let mut alerts = Alerts::load(&path).unwrap();
+    let alert = alerts.add(
+      "MSFT".to_string(),
+      Condition::Below(Num::from(300)),
+      None,
+      false,
+    );
+    assert_eq!(alert.id, 3);
+  }
+
+  /// Check that conditions are evaluated correctly.
+  #[test]
+  fn condition_evaluation() {
+    let above = Condition::Above(Num::from(200));
+    assert!(!above.holds(&Num::new(19999, 100), None));
+    assert!(above.holds(&Num::from(200), None));
+
+    let below = Condition::Below(Num::from(200));
+    assert!(below.holds(&Num::from(199), None));
+    assert!(!below.holds(&Num::from(201), None));
+
+    let close = Num::from(100);
+    let up = Condition::Change(Num::from(2));
+    assert!(!up.holds(&Num::from(102), None));
+    assert!(!up.holds(&Num::new(1019, 10), Some(&close)));
+    assert!(up.holds(&Num::from(102), Some(&close)));
+
+    let down = Condition::Change(Num::from(-2));
+    assert!(!down.holds(&Num::from(102), Some(&close)));
+    assert!(down.holds(&Num::from(97), Some(&close)));
+  }
+
+  /// Check the firing semantics of one-shot and re-arming alerts.
+  #[test]
+  fn watch_firing() {
+    let alert = Alert {
+      id: 1,
+      symbol: "AAPL".to_string(),
+      condition: Condition::Above(Num::from(200)),
+      command: None,
+      rearm: false,
+    };
+    let prices = [199, 201, 202, 199, 201];
+
+    let mut watch = Watch::new(alert.clone());
+    let fired = prices
+      .iter()
+      .map(|price| watch.update(&Num::from(*price), None))
+      .collect::<Vec<_>>();
+    assert_eq!(fired, vec![false, true, false, false, false]);
+    assert!(watch.is_done());
+
+    let mut watch = Watch::new(Alert {
+      rearm: true,
+      ..alert.clone()
+    });
+    let fired = prices
+      .iter()
+      .map(|price| watch.update(&Num::from(*price), None))
+      .collect::<Vec<_>>();
+    assert_eq!(fired, vec![false, true, false, false, true]);
+    assert!(!watch.is_done());
+
+    // An alert whose condition holds from the start only fires once
+    // the price crossed the threshold.
+    let prices = [201, 202, 199, 201];
+    let mut watch = Watch::new(alert);
+    let fired = prices
+      .iter()
+      .map(|price| watch.update(&Num::from(*price), None))
+      .collect::<Vec<_>>();
+    assert_eq!(fired, vec![false, false, false, true]);
+    assert!(watch.is_done());
+  }
+}
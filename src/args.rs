@@ -3,11 +3,14 @@
 
 use std::ffi::OsString;
 use std::fmt::Debug;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 use apca::api::v2::asset;
 use apca::api::v2::order;
 use apca::api::v2::watchlist;
+use apca::data::v2::Feed;
 
 use chrono::NaiveDate;
 use chrono::NaiveDateTime;
@@ -34,14 +37,50 @@ pub struct Args {
   /// Increase verbosity (can be supplied multiple times).
   #[clap(short = 'v', long = "verbose", global = true, action = ArgAction::Count)]
   pub verbosity: u8,
+  /// The format to produce output in.
+  #[clap(long, global = true, value_enum, default_value_t = Output::Text)]
+  pub output: Output,
+  /// The configuration profile to use.
+  ///
+  /// Profiles are defined in `apcacli/config.toml` inside the XDG
+  /// configuration directory. If no profile is provided, the Alpaca
+  /// account is configured through the `APCA_*` environment variables.
+  #[clap(long, global = true, env = "APCACLI_PROFILE")]
+  pub profile: Option<String>,
+  /// Do not ask for confirmation before submitting, changing, or
+  /// canceling orders or closing positions on a live account.
+  ///
+  /// Without this option, such operations fail if stdin does not refer
+  /// to a terminal.
+  #[clap(short = 'y', long, global = true)]
+  pub yes: bool,
+  /// Validate and print the requests that would be sent for submitting,
+  /// changing, or canceling orders or closing positions, instead of
+  /// actually issuing them.
+  #[clap(long, global = true)]
+  pub dry_run: bool,
+}
+
+
+/// An enumeration of the supported output formats.
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum Output {
+  /// Emit human readable text.
+  Text,
+  /// Emit machine readable JSON.
+  Json,
 }
 
+
 /// A command line tool for trading stocks on Alpaca (alpaca.markets).
 #[derive(Debug, Subcommand)]
 pub enum Command {
   /// Retrieve information about the Alpaca account.
   #[clap(subcommand)]
   Account(Account),
+  /// Manage and watch price alerts.
+  #[clap(subcommand)]
+  Alert(Alert),
   /// Retrieve information pertaining assets.
   #[clap(subcommand)]
   Asset(Asset),
@@ -54,9 +93,14 @@ pub enum Command {
   /// Perform various order related functions.
   #[clap(subcommand)]
   Order(Order),
+  /// Perform various portfolio related functions.
+  #[clap(subcommand)]
+  Portfolio(Portfolio),
   /// Perform various position related functions.
   #[clap(subcommand)]
   Position(Position),
+  /// Retrieve the latest quote, trade, and daily change for assets.
+  Quote(Quote),
   /// Subscribe to some update stream.
   #[clap(subcommand)]
   Updates(Updates),
@@ -69,6 +113,35 @@ pub enum Command {
 }
 
 
+/// An amount to risk on a trade.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Risk {
+  /// An absolute value.
+  Value(Num),
+  /// A percentage of account equity.
+  Percent(Num),
+}
+
+impl FromStr for Risk {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (value, percent) = match s.strip_suffix('%') {
+      Some(value) => (value, true),
+      None => (s, false),
+    };
+    let value =
+      Num::from_str(value.trim()).map_err(|_| format!("provided risk '{s}' is invalid"))?;
+    let risk = if percent {
+      Self::Percent(value)
+    } else {
+      Self::Value(value)
+    };
+    Ok(risk)
+  }
+}
+
+
 /// An indication when/for how long an order is valid.
 #[derive(Clone, Debug)]
 pub enum TimeInForce {
@@ -257,18 +330,41 @@ fn parse_date_time(s: &str) -> Result<NaiveDateTime, String> {
 #[derive(Debug, Subcommand)]
 pub enum Bars {
   /// Retrieve historical aggregate bars for a symbol.
-  Get {
-    /// The asset for which to retrieve historical aggregate bars.
-    symbol: String,
-    /// The aggregation time frame.
-    time_frame: TimeFrame,
-    /// The start time for which to retrieve bars.
-    #[clap(value_parser = parse_date_time)]
-    start: NaiveDateTime,
-    /// The end time for which to retrieve bars.
-    #[clap(value_parser = parse_date_time)]
-    end: NaiveDateTime,
-  },
+  Get(GetBars),
+}
+
+/// A type representing the options to retrieve historical bars.
+#[derive(Debug, ClapArgs)]
+pub struct GetBars {
+  /// The asset for which to retrieve historical aggregate bars.
+  pub symbol: String,
+  /// The aggregation time frame.
+  pub time_frame: TimeFrame,
+  /// The start time for which to retrieve bars.
+  #[clap(value_parser = parse_date_time)]
+  pub start: NaiveDateTime,
+  /// The end time for which to retrieve bars.
+  #[clap(value_parser = parse_date_time)]
+  pub end: NaiveDateTime,
+  /// The format to emit bars in (defaults to 'ndjson' if JSON output
+  /// is requested and 'text' otherwise).
+  #[clap(short, long, value_enum)]
+  pub format: Option<BarsFormat>,
+  /// Write bars to the given file instead of stdout.
+  #[clap(long)]
+  pub output_file: Option<PathBuf>,
+}
+
+
+/// An enumeration of the formats in which bars can be emitted.
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum BarsFormat {
+  /// Emit a human readable block per bar.
+  Text,
+  /// Emit comma separated values, with one row per bar.
+  Csv,
+  /// Emit one JSON object per line and bar.
+  Ndjson,
 }
 
 
@@ -284,6 +380,15 @@ pub enum DataSource {
   Sip,
 }
 
+impl DataSource {
+  pub fn to_feed(self) -> Feed {
+    match self {
+      Self::Iex => Feed::IEX,
+      Self::Sip => Feed::SIP,
+    }
+  }
+}
+
 impl FromStr for DataSource {
   type Err = String;
 
@@ -299,43 +404,188 @@ impl FromStr for DataSource {
 }
 
 
+/// A type representing the options of the `quote` command.
+#[derive(Debug, ClapArgs)]
+pub struct Quote {
+  /// The symbols to retrieve quotes for.
+  #[clap(required = true)]
+  pub symbols: Vec<String>,
+  /// The data source to use.
+  #[clap(long, default_value = "iex")]
+  pub source: DataSource,
+}
+
+
 /// A struct representing the `updates` command.
 #[derive(Debug, Subcommand)]
 pub enum Updates {
   /// Subscribe to trade events.
-  Trades,
-  /// Subscribe to realtime market data aggregates.
+  Trades {
+    /// Reconnect with exponential backoff when the connection drops.
+    ///
+    /// Orders updated while disconnected are reported after
+    /// reconnecting.
+    #[clap(long)]
+    reconnect: bool,
+    /// Record received updates to the given file (as NDJSON), for
+    /// later replay.
+    #[clap(long)]
+    record: Option<PathBuf>,
+  },
+  /// Subscribe to realtime market data.
   Data {
-    /// The symbols for which to receive aggregate data.
+    /// The symbols for which to receive market data.
     symbols: Vec<String>,
     /// The data source to use.
     #[clap(long, default_value = "iex")]
     source: DataSource,
+    /// Receive quotes.
+    #[clap(long)]
+    quotes: bool,
+    /// Receive trades.
+    #[clap(long)]
+    trades: bool,
+    /// Receive minute aggregate bars (the default if no other kind of
+    /// data is requested).
+    #[clap(long)]
+    bars: bool,
+    /// Print each update on a single line.
+    #[clap(short, long)]
+    compact: bool,
+    /// Reconnect with exponential backoff and resubscribe when the
+    /// connection drops.
+    #[clap(long)]
+    reconnect: bool,
+    /// Record received updates to the given file (as NDJSON), for
+    /// later replay.
+    #[clap(long)]
+    record: Option<PathBuf>,
+  },
+  /// Replay updates recorded via `--record`.
+  Replay {
+    /// The file containing the recorded updates.
+    file: PathBuf,
+    /// The speed at which to replay updates relative to how they were
+    /// received (e.g., 2 for twice as fast). Use 0 to replay them
+    /// without any delay.
+    #[clap(long, default_value = "1")]
+    speed: f64,
+    /// Print each market data update on a single line.
+    #[clap(short, long)]
+    compact: bool,
+    /// The currency in which to report notional amounts.
+    #[clap(long, default_value = "USD")]
+    currency: String,
+  },
+}
+
+
+/// An enumeration representing the `alert` command.
+#[derive(Debug, Subcommand)]
+pub enum Alert {
+  /// Add a price alert.
+  ///
+  /// Alerts fire only when the price crosses their threshold while
+  /// being watched, not if the condition already holds when watching
+  /// starts.
+  Add(AddAlert),
+  /// List all price alerts.
+  List,
+  /// Remove price alerts.
+  Remove {
+    /// The IDs of the alerts to remove.
+    #[clap(required = true)]
+    ids: Vec<u64>,
+  },
+  /// Watch market data and fire alerts whose conditions are met.
+  ///
+  /// Alerts fire when the price crosses their threshold, i.e., an
+  /// alert whose condition already holds when watching starts only
+  /// fires after the condition ceased to hold once. Alerts that fire
+  /// only once are removed after firing. The command exits once no
+  /// alerts remain to be watched.
+  Watch {
+    /// The data source to use.
+    #[clap(long, default_value = "iex")]
+    source: DataSource,
+    /// Evaluate alerts against the close prices of minute bars instead
+    /// of against trades.
+    #[clap(long)]
+    bars: bool,
+    /// Reconnect with exponential backoff and resubscribe when the
+    /// connection drops.
+    #[clap(long)]
+    reconnect: bool,
   },
 }
 
 
+/// A type representing the options to add a price alert.
+#[derive(Debug, ClapArgs)]
+#[clap(group = ArgGroup::new("condition").required(true))]
+pub struct AddAlert {
+  /// The symbol whose price to watch.
+  pub symbol: String,
+  /// Fire when the price is at or above the given value.
+  #[clap(long, group = "condition")]
+  pub above: Option<Num>,
+  /// Fire when the price is at or below the given value.
+  #[clap(long, group = "condition")]
+  pub below: Option<Num>,
+  /// Fire when the price changed by at least the given percentage
+  /// relative to the previous day's close (e.g., 3 or -3).
+  #[clap(long, group = "condition", allow_hyphen_values = true)]
+  pub change: Option<Num>,
+  /// A shell command to run when the alert fires. Details are passed in
+  /// the APCA_ALERT_ID, APCA_ALERT_SYMBOL, APCA_ALERT_CONDITION, and
+  /// APCA_ALERT_PRICE environment variables. Without a command, a line
+  /// is printed instead.
+  #[clap(short, long)]
+  pub exec: Option<String>,
+  /// Re-arm the alert once its condition no longer holds, instead of
+  /// firing only once.
+  #[clap(short, long)]
+  pub rearm: bool,
+}
+
+
 /// An enumeration representing the `order` command.
 #[derive(Debug, Subcommand)]
 pub enum Order {
   /// Submit an order.
   #[clap(group = ArgGroup::new("amount").required(true))]
   Submit(SubmitOrder),
+  /// Submit multiple orders read from a file.
+  SubmitBatch(SubmitBatch),
   /// Change an order.
   #[clap(group = ArgGroup::new("amount"))]
   Change(ChangeOrder),
-  /// Cancel a single order (by id) or all open ones (via 'all').
-  Cancel { cancel: CancelOrder },
+  /// Cancel orders by ID, all open ones (via 'all'), or those matching
+  /// the given filters.
+  ///
+  /// When IDs are provided, filters further restrict the orders to
+  /// cancel. Otherwise they are applied to all open orders.
+  #[clap(group = ArgGroup::new("selection").required(true).multiple(true))]
+  Cancel(CancelOrders),
   /// Retrieve information about a single order.
   Get {
-    /// The ID of the order to retrieve information about.
-    id: OrderId,
+    /// The ID or client order ID of the order to retrieve information
+    /// about.
+    id: String,
   },
   /// List orders.
-  List {
-    /// Show only closed orders instead of open ones.
-    #[clap(short = 'c', long)]
-    closed: bool,
+  List(ListOrders),
+  /// Wait for an order to be filled, canceled, expired, or rejected.
+  ///
+  /// The program exits with status 0 if the order got filled, 3 if it
+  /// was canceled (or replaced), 4 if it expired, 5 if it was
+  /// rejected, and 6 on timeout.
+  Wait {
+    /// The ID or client order ID of the order to wait for.
+    id: String,
+    /// The maximum number of seconds to wait.
+    #[clap(short, long)]
+    timeout: Option<u64>,
   },
 }
 
@@ -353,12 +603,42 @@ pub struct SubmitOrder {
   /// The value to trade.
   #[clap(long, group = "amount")]
   pub value: Option<Num>,
+  /// The value to trade, as a percentage of account equity.
+  #[clap(long, group = "amount")]
+  pub percent_equity: Option<Num>,
+  /// The value to trade, as a percentage of buying power.
+  #[clap(long, group = "amount")]
+  pub percent_buying_power: Option<Num>,
+  /// The amount to risk, either as a value (e.g., '500') or as a
+  /// percentage of account equity (e.g., '1%').
+  ///
+  /// The quantity is derived from the distance between the entry price
+  /// (the limit price or the last quote) and the stop-loss stop price
+  /// or, if absent, the stop price, one of which is required.
+  #[clap(long, group = "amount")]
+  pub risk: Option<Risk>,
+  /// Keep the fractional quantity derived from a value instead of
+  /// rounding it to whole shares. The asset has to be fractionable.
+  #[clap(long, conflicts_with_all = ["quantity", "risk", "notional"])]
+  pub fractional: bool,
+  /// Submit a notional order for the given value instead of converting
+  /// the value into a quantity.
+  #[clap(long, conflicts_with_all = ["quantity", "risk"])]
+  pub notional: bool,
   /// Create a limit order (or stop limit order) with the given limit price.
   #[clap(short = 'l', long)]
   pub limit_price: Option<Num>,
   /// Create a stop order (or stop limit order) with the given stop price.
   #[clap(short = 's', long)]
   pub stop_price: Option<Num>,
+  /// Create a trailing stop order with the stop price trailing the high
+  /// water mark by the given dollar amount.
+  #[clap(long, conflicts_with_all = ["trail_percent", "limit_price", "stop_price"])]
+  pub trail_price: Option<Num>,
+  /// Create a trailing stop order with the stop price trailing the high
+  /// water mark by the given percentage (e.g., 5 for 5%).
+  #[clap(long, conflicts_with_all = ["limit_price", "stop_price"])]
+  pub trail_percent: Option<Num>,
   /// Create a one-triggers-other or bracket order with the given
   /// take-profit price.
   #[clap(long)]
@@ -372,17 +652,123 @@ pub struct SubmitOrder {
   /// option can only be used in conjunction with stop-loss-stop-price.
   #[clap(long)]
   pub stop_loss_limit_price: Option<Num>,
+  /// Create a one-cancels-other order for exiting an existing position,
+  /// consisting of a take-profit limit leg and a stop-loss leg. Requires
+  /// take-profit-price and stop-loss-stop-price.
+  #[clap(
+    long,
+    requires_all = ["take_profit_price", "stop_loss_stop_price"],
+    conflicts_with_all = ["limit_price", "stop_price", "trail_price", "trail_percent"],
+  )]
+  pub oco: bool,
   /// Create an order that is eligible to execute during
   /// pre-market/after hours. Note that only limit orders that are
   /// valid for the day are supported.
   #[clap(long)]
   pub extended_hours: bool,
   /// When/for how long the order is valid ('today', 'canceled',
-  /// 'market-open', or 'market-close').
-  #[clap(short = 't', long, default_value = "canceled")]
-  pub time_in_force: TimeInForce,
+  /// 'market-open', or 'market-close'). Defaults to 'canceled', except
+  /// for fractional and notional orders, which Alpaca only accepts as
+  /// 'today'.
+  #[clap(short = 't', long)]
+  pub time_in_force: Option<TimeInForce>,
+  /// The client order ID to associate with the order (at most 48
+  /// characters). A random UUID is used if none is provided.
+  ///
+  /// Alpaca rejects orders with a client order ID that is already in
+  /// use, making it safe to retry a submission that may or may not
+  /// have gone through.
+  #[clap(long)]
+  pub client_order_id: Option<String>,
+  /// Wait for the order to be filled (or canceled, expired, or
+  /// rejected) or to rest at its price target and for its legs, if
+  /// any, to be placed, reporting fills on stderr along the way.
+  #[clap(long)]
+  pub wait: bool,
+  /// The maximum number of seconds to wait when `--wait` is given.
+  #[clap(long, requires = "wait")]
+  pub timeout: Option<u64>,
+}
+
+/// A type representing the options to submit a batch of orders.
+#[derive(Debug, ClapArgs)]
+pub struct SubmitBatch {
+  /// The file to read orders from.
+  ///
+  /// Each order is described by the fields `side`, `symbol`,
+  /// `quantity` or `value`, and, optionally, `limit_price`,
+  /// `stop_price`, `trail_price`, `trail_percent`, `take_profit_price`,
+  /// `stop_loss_stop_price`, `stop_loss_limit_price`, `oco`,
+  /// `extended_hours`, `time_in_force`, and `client_order_id`, with the
+  /// same meaning as the corresponding options of the 'submit'
+  /// command.
+  pub path: PathBuf,
+  /// The format of the file (inferred from its extension by default).
+  #[clap(short, long, value_enum)]
+  pub format: Option<BatchFormat>,
+  /// Do not submit any more orders once a submission failed.
+  ///
+  /// Orders are submitted one after the other in this mode, instead of
+  /// concurrently.
+  #[clap(long)]
+  pub stop_on_error: bool,
+}
+
+
+/// An enumeration of the supported formats of order batch files.
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum BatchFormat {
+  /// Comma separated values, with a header row naming the fields.
+  Csv,
+  /// One JSON object per line.
+  Ndjson,
+}
+
+
+/// A type representing the options to list orders.
+#[derive(Debug, ClapArgs)]
+pub struct ListOrders {
+  /// Show only closed orders instead of open ones.
+  #[clap(short = 'c', long, conflicts_with = "all")]
+  pub closed: bool,
+  /// Show open as well as closed orders.
+  #[clap(short = 'a', long)]
+  pub all: bool,
+  /// Show only orders for the given symbol (can be supplied multiple
+  /// times).
+  #[clap(short = 's', long = "symbol")]
+  pub symbols: Vec<String>,
+  /// Show only orders on the given side.
+  #[clap(long, value_enum)]
+  pub side: Option<Side>,
+  /// Show only orders submitted after the given date/time (interpreted
+  /// in the America/New_York time zone).
+  #[clap(long, value_parser = parse_date_time)]
+  pub after: Option<NaiveDateTime>,
+  /// Show only orders submitted before the given date/time
+  /// (interpreted in the America/New_York time zone).
+  #[clap(long, value_parser = parse_date_time)]
+  pub until: Option<NaiveDateTime>,
+  /// The order in which to list orders, by submission time.
+  #[clap(long, value_enum, default_value_t = Direction::Desc)]
+  pub direction: Direction,
+  /// The maximum number of orders to list (all matching orders by
+  /// default).
+  #[clap(short = 'l', long)]
+  pub limit: Option<usize>,
+}
+
+
+/// An enumeration of the directions in which to sort items.
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum Direction {
+  /// Oldest items first.
+  Asc,
+  /// Newest items first.
+  Desc,
 }
 
+
 /// A type representing the options to change an order.
 #[derive(Debug, ClapArgs)]
 pub struct ChangeOrder {
@@ -400,6 +786,14 @@ pub struct ChangeOrder {
   /// Create a stop order (or stop limit order) with the given stop price.
   #[clap(short = 's', long)]
   pub stop_price: Option<Num>,
+  /// Change the dollar amount by which the stop price of a trailing
+  /// stop order trails the high water mark.
+  #[clap(long, conflicts_with_all = ["trail_percent", "limit_price", "stop_price"])]
+  pub trail_price: Option<Num>,
+  /// Change the percentage by which the stop price of a trailing stop
+  /// order trails the high water mark.
+  #[clap(long, conflicts_with_all = ["limit_price", "stop_price"])]
+  pub trail_percent: Option<Num>,
   /// When/for how long the order is valid ('today', 'canceled',
   /// 'market-open', or 'market-close').
   #[clap(short = 't', long)]
@@ -407,24 +801,75 @@ pub struct ChangeOrder {
 }
 
 
-/// An enumeration of the different options for order cancellation.
-#[derive(Clone, Debug)]
-pub enum CancelOrder {
-  /// Cancel a single order as specified by an `OrderId`.
-  ById(OrderId),
-  /// Cancel all open orders.
-  All,
+/// A type representing the options to cancel orders.
+#[derive(Debug, ClapArgs)]
+pub struct CancelOrders {
+  /// The IDs or client order IDs of the orders to cancel, or 'all' to
+  /// cancel all open orders.
+  #[clap(group = "selection")]
+  pub ids: Vec<String>,
+  /// Cancel only orders for the given symbol (can be supplied multiple
+  /// times).
+  #[clap(short = 's', long = "symbol", group = "selection")]
+  pub symbols: Vec<String>,
+  /// Cancel only orders on the given side.
+  #[clap(long, value_enum, group = "selection")]
+  pub side: Option<Side>,
+  /// Cancel only orders of the given type.
+  #[clap(long = "type", value_enum, group = "selection")]
+  pub type_: Option<OrderType>,
+  /// Cancel only orders submitted longer ago than the given duration
+  /// (e.g., '90m', '12h', or '2d').
+  #[clap(long, value_parser = parse_duration, group = "selection")]
+  pub older_than: Option<Duration>,
 }
 
-impl FromStr for CancelOrder {
-  type Err = UuidError;
 
-  fn from_str(s: &str) -> Result<Self, Self::Err> {
-    let cancel = match s {
-      "all" => CancelOrder::All,
-      s => CancelOrder::ById(OrderId::from_str(s)?),
-    };
-    Ok(cancel)
+/// Parse a duration consisting of a number and a unit ('s', 'm', 'h',
+/// 'd', or 'w').
+fn parse_duration(s: &str) -> Result<Duration, String> {
+  let (count, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+  let count = u64::from_str(count).map_err(|_| format!("provided duration '{s}' is invalid"))?;
+  let seconds = match unit {
+    "s" => 1,
+    "m" => 60,
+    "h" => 60 * 60,
+    "d" => 24 * 60 * 60,
+    "w" => 7 * 24 * 60 * 60,
+    _ => return Err(format!("provided duration '{s}' has invalid unit")),
+  };
+  let seconds = count
+    .checked_mul(seconds)
+    .ok_or_else(|| format!("provided duration '{s}' is too large"))?;
+  Ok(Duration::from_secs(seconds))
+}
+
+
+/// An enumeration of the types an order can have.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OrderType {
+  /// A market order.
+  Market,
+  /// A limit order.
+  Limit,
+  /// A stop order.
+  Stop,
+  /// A stop limit order.
+  StopLimit,
+  /// A trailing stop order.
+  TrailingStop,
+}
+
+impl OrderType {
+  /// Convert the order type into its apca counterpart.
+  pub fn to_order_type(self) -> order::Type {
+    match self {
+      Self::Market => order::Type::Market,
+      Self::Limit => order::Type::Limit,
+      Self::Stop => order::Type::Stop,
+      Self::StopLimit => order::Type::StopLimit,
+      Self::TrailingStop => order::Type::TrailingStop,
+    }
   }
 }
 
@@ -473,14 +918,79 @@ pub enum Position {
   },
   /// List all open positions.
   List,
-  /// Liquidate a position for a certain asset.
+  /// Liquidate a position (or part of it) for a certain asset.
   Close {
     /// The position's symbol.
     symbol: Symbol,
+    /// The quantity to close instead of the entire position.
+    #[clap(short, long, conflicts_with = "percentage")]
+    quantity: Option<Num>,
+    /// The percentage (between 0 and 100) of the position to close
+    /// instead of the entire position.
+    #[clap(short, long)]
+    percentage: Option<Num>,
+  },
+  /// Liquidate all open positions.
+  ///
+  /// Confirmation is required unless --yes is provided, on paper
+  /// trading accounts as well.
+  CloseAll {
+    /// Cancel all open orders (except for excluded symbols) before
+    /// closing positions.
+    #[clap(short, long)]
+    cancel_orders: bool,
+    /// A comma-separated list of symbols whose positions (and orders)
+    /// to leave untouched.
+    #[clap(short, long, value_parser = parse_symbol_list)]
+    exclude: Vec<Vec<String>>,
   },
 }
 
 
+/// An enumeration representing the `portfolio` command.
+#[derive(Debug, Subcommand)]
+pub enum Portfolio {
+  /// Compute (and optionally execute) the trades necessary to bring
+  /// positions to target weights.
+  Rebalance(Rebalance),
+}
+
+
+/// A type representing the options to rebalance the portfolio.
+#[derive(Debug, ClapArgs)]
+pub struct Rebalance {
+  /// A TOML file mapping symbols to target weights, in percent of
+  /// account equity (e.g., `SPY = 60`). Positions in symbols not
+  /// listed are left alone.
+  pub targets: PathBuf,
+  /// The distance from the target weight, in percentage points, within
+  /// which positions are left alone.
+  #[clap(short, long, default_value = "1")]
+  pub tolerance: Num,
+  /// How to round quantities to trade.
+  #[clap(short, long, value_enum, default_value_t = Rounding::Whole)]
+  pub rounding: Rounding,
+  /// Submit the orders, selling before buying, instead of just
+  /// printing the plan.
+  #[clap(short, long)]
+  pub execute: bool,
+  /// How long to wait for sell orders to be filled before giving up
+  /// on submitting buy orders (e.g., '90s', '15m', or '2h').
+  #[clap(long, value_parser = parse_duration, default_value = "5m", requires = "execute")]
+  pub timeout: Duration,
+}
+
+
+/// An enumeration of the ways quantities can be rounded.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Rounding {
+  /// Round to whole shares.
+  Whole,
+  /// Round to fractional shares, for assets that support it.
+  Fractional,
+}
+
+
 /// Parse a comma-separated list of symbols.
 fn parse_symbol_list(s: &str) -> Result<Vec<String>, String> {
   let symbols = s
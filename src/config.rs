@@ -0,0 +1,501 @@
+// Copyright (C) 2026 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+use std::env::var_os;
+use std::fs::read_to_string;
+use std::fs::File;
+use std::io::Read as _;
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+use apca::ApiInfo;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use serde::Deserialize;
+
+
+/// The base URL of Alpaca's paper trading API.
+const PAPER_API_BASE_URL: &str = "https://paper-api.alpaca.markets";
+/// The host serving Alpaca's live trading API.
+const LIVE_API_HOST: &str = "api.alpaca.markets";
+
+
+/// Extract the secret from the output of a command or the contents of
+/// a file.
+///
+/// Only the first line is considered, similar to what `pass` and
+/// friends do, so that additional meta data can be stored alongside.
+fn first_line(content: &str) -> Result<String> {
+  let line = content.lines().next().unwrap_or_default().trim();
+  if line.is_empty() {
+    bail!("no secret found")
+  }
+  Ok(line.to_string())
+}
+
+
+/// Run the given command and retrieve the secret it reports.
+fn run_command(argv: &[String]) -> Result<String> {
+  let (program, args) = argv.split_first().context("command is empty")?;
+  // We leave stdin and stderr connected to the terminal so that
+  // programs such as `pass` can interact with the user, e.g., to
+  // unlock a key.
+  let output = Command::new(program)
+    .args(args)
+    .stdin(Stdio::inherit())
+    .stderr(Stdio::inherit())
+    .output()
+    .with_context(|| format!("failed to run `{program}`"))?;
+
+  if !output.status.success() {
+    bail!("`{program}` failed: {}", output.status)
+  }
+
+  let stdout = String::from_utf8(output.stdout)
+    .map_err(|_| anyhow!("output of `{program}` is not valid UTF-8"))?;
+  first_line(&stdout).with_context(|| format!("`{program}` did not report a secret"))
+}
+
+
+/// Read the secret stored in the file at the given path.
+///
+/// Files accessible by anybody but the owner are refused.
+fn read_file(path: &Path) -> Result<String> {
+  // We check permissions on the opened file and read from the very
+  // same handle, so that the file can't be swapped out in between.
+  // For symbolic links, it is the file linked to that gets checked.
+  let mut file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+  let meta = file
+    .metadata()
+    .with_context(|| format!("failed to query metadata of {}", path.display()))?;
+  let mode = meta.permissions().mode();
+  if mode & 0o077 != 0 {
+    bail!(
+      "{} is accessible by group or others (mode {:o}); restrict it to the owner (e.g., `chmod 600`)",
+      path.display(),
+      mode & 0o777,
+    )
+  }
+
+  let mut content = String::new();
+  let _count = file
+    .read_to_string(&mut content)
+    .with_context(|| format!("failed to read {}", path.display()))?;
+  first_line(&content).with_context(|| format!("{} does not contain a secret", path.display()))
+}
+
+
+/// A source from which a secret can be retrieved.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SecretSource {
+  /// Read the secret from the environment variable with the given
+  /// name.
+  Env(String),
+  /// Run the given command (the program followed by its arguments)
+  /// and use the first line of its output.
+  Command(Vec<String>),
+  /// Use the first line of the file at the given path.
+  File(PathBuf),
+}
+
+impl SecretSource {
+  /// Retrieve the secret.
+  fn retrieve(&self) -> Result<String> {
+    match self {
+      Self::Env(name) => var_os(name)
+        .with_context(|| format!("environment variable `{name}` not found"))?
+        .into_string()
+        .map_err(|_| anyhow!("environment variable `{name}` is not valid UTF-8")),
+      Self::Command(argv) => run_command(argv),
+      Self::File(path) => read_file(path),
+    }
+  }
+}
+
+
+/// A value that is either provided inline or retrieved from a
+/// `SecretSource`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Value {
+  /// The value itself.
+  Inline(String),
+  /// The source to retrieve the value from.
+  Source(SecretSource),
+}
+
+impl Value {
+  /// Retrieve the value.
+  fn retrieve(&self) -> Result<String> {
+    match self {
+      Self::Inline(value) => Ok(value.clone()),
+      Self::Source(source) => source.retrieve(),
+    }
+  }
+}
+
+
+/// The base URL used by profiles not specifying one: the paper API.
+fn default_base_url() -> String {
+  PAPER_API_BASE_URL.to_string()
+}
+
+
+/// A named profile describing how to access an Alpaca account.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+  /// The base URL of the trading API.
+  #[serde(default = "default_base_url")]
+  pub base_url: String,
+  /// The base URL of the market data API.
+  pub data_url: Option<String>,
+  /// The base URL of the market data streaming API.
+  pub data_stream_url: Option<String>,
+  /// The key ID to authenticate with.
+  pub key_id: Value,
+  /// The source of the secret to authenticate with.
+  pub secret: SecretSource,
+  /// Whether the profile refers to a live account.
+  ///
+  /// Profiles using the live API endpoint have to be marked as such
+  /// explicitly.
+  #[serde(default)]
+  pub live: bool,
+}
+
+impl Profile {
+  /// Check that the profile is sane.
+  fn validate(&self, name: &str) -> Result<()> {
+    let is_live = is_live_url(&self.base_url)
+      .with_context(|| format!("profile `{name}` contains invalid base URL"))?;
+    if is_live && !self.live {
+      bail!("profile `{name}` uses the live API endpoint but is not marked as `live = true`")
+    }
+    Ok(())
+  }
+
+  /// Create an `ApiInfo` object for accessing the profile's account.
+  fn api_info(&self) -> Result<ApiInfo> {
+    let key_id = self
+      .key_id
+      .retrieve()
+      .context("failed to retrieve key ID")?;
+    let secret = self
+      .secret
+      .retrieve()
+      .context("failed to retrieve secret")?;
+    let mut api_info = ApiInfo::from_parts(&self.base_url, key_id, secret)?;
+
+    if let Some(url) = &self.data_url {
+      api_info.data_base_url = url
+        .parse()
+        .with_context(|| format!("invalid data URL: {url}"))?;
+    }
+    if let Some(url) = &self.data_stream_url {
+      api_info.data_stream_base_url = url
+        .parse()
+        .with_context(|| format!("invalid data stream URL: {url}"))?;
+    }
+    Ok(api_info)
+  }
+
+  /// Create an `Access` object for accessing the profile's account.
+  pub fn access(&self) -> Result<Access> {
+    let api_info = self.api_info()?;
+    // The profile may refer to a live account through an endpoint
+    // other than Alpaca's, e.g., a proxy.
+    let live = self.live || is_live(&api_info);
+    Ok(Access { api_info, live })
+  }
+}
+
+
+/// Information on how to access an Alpaca account.
+#[derive(Debug)]
+pub struct Access {
+  /// The information required for accessing the API.
+  pub api_info: ApiInfo,
+  /// Whether the account is a live one.
+  pub live: bool,
+}
+
+
+/// The program's configuration.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+  /// The available profiles, keyed by name.
+  #[serde(default)]
+  pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+  /// Parse a configuration from a TOML string.
+  fn from_toml(toml: &str) -> Result<Self> {
+    let config = toml::from_str::<Self>(toml)?;
+    let () = config
+      .profiles
+      .iter()
+      .try_for_each(|(name, profile)| profile.validate(name))?;
+    Ok(config)
+  }
+
+  /// Load the configuration from the file at the given path.
+  pub fn load(path: &Path) -> Result<Self> {
+    let toml = read_to_string(path)
+      .with_context(|| format!("failed to read configuration file {}", path.display()))?;
+    Self::from_toml(&toml)
+      .with_context(|| format!("failed to parse configuration file {}", path.display()))
+  }
+
+  /// Look up the profile with the given name.
+  pub fn profile(&self, name: &str) -> Result<&Profile> {
+    self
+      .profiles
+      .get(name)
+      .with_context(|| format!("profile `{name}` not found"))
+  }
+}
+
+
+/// Check whether the given URL refers to Alpaca's live trading API.
+fn is_live_url(url: &str) -> Result<bool> {
+  // We don't want to pull in a URL parsing crate just for this check.
+  // The scheme is required by `ApiInfo` anyway, so require it here.
+  let (_scheme, rest) = url
+    .split_once("://")
+    .with_context(|| format!("URL `{url}` lacks a scheme"))?;
+  let host = rest.split(['/', ':']).next().unwrap_or_default();
+  Ok(host.eq_ignore_ascii_case(LIVE_API_HOST))
+}
+
+
+/// Check whether the given `ApiInfo` object refers to Alpaca's live
+/// trading API.
+fn is_live(api_info: &ApiInfo) -> bool {
+  api_info
+    .api_base_url
+    .host_str()
+    .map(|host| host.eq_ignore_ascii_case(LIVE_API_HOST))
+    .unwrap_or(false)
+}
+
+
+/// Retrieve the path to the `apcacli` directory below the XDG
+/// configuration directory, e.g., `~/.config/apcacli`.
+fn config_dir() -> Result<PathBuf> {
+  let dir = var_os("XDG_CONFIG_HOME")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    .context(
+      "unable to determine configuration directory: neither XDG_CONFIG_HOME nor HOME are set",
+    )?;
+
+  Ok(dir.join("apcacli"))
+}
+
+
+/// Retrieve the path to the configuration file.
+///
+/// The file is located in the `apcacli` directory below the XDG
+/// configuration directory, e.g., `~/.config/apcacli/config.toml`.
+pub fn config_path() -> Result<PathBuf> {
+  Ok(config_dir()?.join("config.toml"))
+}
+
+
+/// Retrieve the path to the file storing price alerts, e.g.,
+/// `~/.config/apcacli/alerts.toml`.
+pub fn alerts_path() -> Result<PathBuf> {
+  Ok(config_dir()?.join("alerts.toml"))
+}
+
+
+/// Create an `Access` object for the profile with the given name or,
+/// if no profile is provided, from the `APCA_*` environment variables.
+pub fn access(profile: Option<&str>) -> Result<Access> {
+  match profile {
+    Some(name) => {
+      let path = config_path()?;
+      let config = Config::load(&path)?;
+      config
+        .profile(name)?
+        .access()
+        .with_context(|| format!("failed to set up profile `{name}`"))
+    },
+    None => {
+      let api_info =
+        ApiInfo::from_env().with_context(|| "failed to retrieve Alpaca environment information")?;
+      let live = is_live(&api_info);
+      Ok(Access { api_info, live })
+    },
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::fs::set_permissions;
+  use std::fs::write;
+  use std::fs::Permissions;
+
+  use tempfile::tempdir;
+
+
+  /// Check that we can parse a configuration containing multiple
+  /// profiles.
+  #[test]
+  fn parse_config() {
+    let toml = r#"
+      [profiles.paper]
+      key-id = "PKXXXXXXXXXXXXXXXXXX"
+      secret = { env = "APCA_PAPER_SECRET" }
+
+      [profiles.live]
+      base-url = "https://api.alpaca.markets"
+      data-url = "https://data.alpaca.markets"
+      key-id = { command = ["pass", "show", "alpaca/live-key-id"] }
+      secret = { file = "/home/user/.alpaca/live-secret" }
+      live = true
+    "#;
+    let config = Config::from_toml(toml).unwrap();
+
+    let paper = config.profile("paper").unwrap();
+    assert_eq!(paper.base_url, PAPER_API_BASE_URL);
+    assert_eq!(paper.data_url, None);
+    assert_eq!(
+      paper.key_id,
+      Value::Inline("PKXXXXXXXXXXXXXXXXXX".to_string())
+    );
+    assert_eq!(
+      paper.secret,
+      SecretSource::Env("APCA_PAPER_SECRET".to_string())
+    );
+    assert!(!paper.live);
+
+    let live = config.profile("live").unwrap();
+    assert_eq!(live.base_url, "https://api.alpaca.markets");
+    assert_eq!(
+      live.data_url.as_deref(),
+      Some("https://data.alpaca.markets")
+    );
+    assert_eq!(
+      live.key_id,
+      Value::Source(SecretSource::Command(vec![
+        "pass".to_string(),
+        "show".to_string(),
+        "alpaca/live-key-id".to_string(),
+      ]))
+    );
+    assert_eq!(
+      live.secret,
+      SecretSource::File(PathBuf::from("/home/user/.alpaca/live-secret"))
+    );
+    assert!(live.live);
+
+    let err = config.profile("foo").unwrap_err();
+    assert_eq!(err.to_string(), "profile `foo` not found");
+  }
+
+  /// Check that profiles marked as live are treated as such, even if
+  /// they do not use Alpaca's live API endpoint.
+  #[test]
+  fn live_profile_access() {
+    let toml = r#"
+      [profiles.proxy]
+      base-url = "https://alpaca.example.com"
+      key-id = "AKXXXXXXXXXXXXXXXXXX"
+      secret = { command = ["echo", "secret"] }
+      live = true
+
+      [profiles.paper]
+      key-id = "PKXXXXXXXXXXXXXXXXXX"
+      secret = { command = ["echo", "secret"] }
+    "#;
+    let config = Config::from_toml(toml).unwrap();
+
+    let access = config.profile("proxy").unwrap().access().unwrap();
+    assert!(access.live);
+    let access = config.profile("paper").unwrap().access().unwrap();
+    assert!(!access.live);
+  }
+
+  /// Make sure that profiles using the live API endpoint need to be
+  /// marked as live explicitly.
+  #[test]
+  fn unmarked_live_profile() {
+    let toml = r#"
+      [profiles.live]
+      base-url = "https://api.alpaca.markets/"
+      key-id = "AKXXXXXXXXXXXXXXXXXX"
+      secret = { env = "APCA_LIVE_SECRET" }
+    "#;
+    let err = Config::from_toml(toml).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "profile `live` uses the live API endpoint but is not marked as `live = true`"
+    );
+  }
+
+  /// Check that we can retrieve a secret by running a command.
+  #[test]
+  fn command_secret() {
+    let source = SecretSource::Command(vec!["echo".to_string(), "s3cr3t".to_string()]);
+    assert_eq!(source.retrieve().unwrap(), "s3cr3t");
+
+    let source = SecretSource::Command(vec!["false".to_string()]);
+    assert!(source.retrieve().is_err());
+
+    let source = SecretSource::Command(Vec::new());
+    assert!(source.retrieve().is_err());
+  }
+
+  /// Check that we can read a secret from a file, but only if it is
+  /// not accessible by others.
+  #[test]
+  fn file_secret() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("secret");
+    let () = write(&path, "s3cr3t\nsome other data\n").unwrap();
+    let () = set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+
+    let source = SecretSource::File(path.clone());
+    assert_eq!(source.retrieve().unwrap(), "s3cr3t");
+
+    let () = set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+    let err = source.retrieve().unwrap_err();
+    assert!(
+      err
+        .to_string()
+        .contains("is accessible by group or others (mode 640)"),
+      "{err}"
+    );
+  }
+
+  /// Check that we detect the live API endpoint correctly.
+  #[test]
+  fn live_url_detection() {
+    assert!(is_live_url("https://api.alpaca.markets").unwrap());
+    assert!(is_live_url("https://api.alpaca.markets:443/v2").unwrap());
+    assert!(!is_live_url("https://paper-api.alpaca.markets").unwrap());
+    assert!(is_live_url("api.alpaca.markets").is_err());
+
+    let api_info = ApiInfo::from_parts("https://api.alpaca.markets/", "", "").unwrap();
+    assert!(is_live(&api_info));
+    let api_info = ApiInfo::from_parts(PAPER_API_BASE_URL, "", "").unwrap();
+    assert!(!is_live(&api_info));
+  }
+}
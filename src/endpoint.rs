@@ -0,0 +1,474 @@
+// Copyright (C) 2026 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Definitions of Alpaca API endpoints that `apca` does not (fully)
+//! cover.
+
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+
+use apca::api::v2::asset;
+use apca::api::v2::order;
+use apca::api::v2::orders;
+use apca::data::v2::Feed;
+use apca::ApiError;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use http::Method;
+
+use http_endpoint::EndpointDef;
+use http_endpoint::Str;
+
+use num_decimal::Num;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde::Serializer;
+use serde_json::from_slice as from_json;
+use serde_json::Error as JsonError;
+use serde_urlencoded::ser::Error as UrlEncodeError;
+use serde_urlencoded::to_string as to_query;
+
+
+/// The base URL of the market data API.
+///
+/// `apca` uses the same URL for its data endpoints, but does not
+/// export it.
+const DATA_BASE_URL: &str = "https://data.alpaca.markets";
+
+
+/// An error type comprising the conversion errors we may encounter
+/// when issuing requests.
+#[derive(Debug)]
+pub enum ConversionError {
+  /// A JSON conversion failed.
+  Json(JsonError),
+  /// Some data could not be URL-encoded.
+  UrlEncode(UrlEncodeError),
+}
+
+impl Display for ConversionError {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    match self {
+      Self::Json(..) => fmt.write_str("failed to convert from/to JSON"),
+      Self::UrlEncode(..) => fmt.write_str("failed to URL-encode data"),
+    }
+  }
+}
+
+impl StdError for ConversionError {
+  fn source(&self) -> Option<&(dyn StdError + 'static)> {
+    match self {
+      Self::Json(err) => Some(err),
+      Self::UrlEncode(err) => Some(err),
+    }
+  }
+}
+
+impl From<JsonError> for ConversionError {
+  fn from(err: JsonError) -> Self {
+    Self::Json(err)
+  }
+}
+
+impl From<UrlEncodeError> for ConversionError {
+  fn from(err: UrlEncodeError) -> Self {
+    Self::UrlEncode(err)
+  }
+}
+
+
+/// The direction in which orders are sorted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub enum Direction {
+  /// Oldest orders first.
+  #[serde(rename = "asc")]
+  Ascending,
+  /// Newest orders first.
+  #[default]
+  #[serde(rename = "desc")]
+  Descending,
+}
+
+
+/// Serialize a list of symbols as a comma separated string.
+fn serialize_symbols<S>(symbols: &[String], serializer: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  serializer.serialize_str(&symbols.join(","))
+}
+
+
+/// A GET request to be made to the /v2/orders endpoint.
+///
+/// In contrast to `apca`'s `orders::ListReq`, this request supports
+/// all the filters the endpoint offers, which are necessary for
+/// paginating through results.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ListOrdersReq {
+  /// The status of orders to list.
+  pub status: orders::Status,
+  /// The maximum number of orders contained in the response (at most
+  /// 500).
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub limit: Option<usize>,
+  /// Only include orders submitted after this time (exclusive).
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub after: Option<DateTime<Utc>>,
+  /// Only include orders submitted until this time (exclusive).
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub until: Option<DateTime<Utc>>,
+  /// The direction in which orders are sorted by submission time.
+  pub direction: Direction,
+  /// Whether to roll up multi-leg orders under the `legs` field of the
+  /// primary order.
+  pub nested: bool,
+  /// Only include orders for these symbols.
+  #[serde(
+    skip_serializing_if = "Vec::is_empty",
+    serialize_with = "serialize_symbols"
+  )]
+  pub symbols: Vec<String>,
+  /// Only include orders on this side.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub side: Option<order::Side>,
+}
+
+impl Default for ListOrdersReq {
+  fn default() -> Self {
+    Self {
+      status: orders::Status::Open,
+      limit: None,
+      after: None,
+      until: None,
+      direction: Direction::default(),
+      nested: true,
+      symbols: Vec::new(),
+      side: None,
+    }
+  }
+}
+
+
+EndpointDef! {
+  /// The representation of a GET request to the /v2/orders endpoint.
+  pub ListOrders(ListOrdersReq),
+  Ok => Vec<order::Order>, [
+    /* 200 */ OK,
+  ],
+  Err => ListOrdersError, [
+    /// The request was not permitted.
+    /* 403 */ FORBIDDEN => NotPermitted,
+    /// The rate limit was exceeded, causing the request to be denied.
+    /* 429 */ TOO_MANY_REQUESTS => RateLimitExceeded,
+  ],
+  ConversionErr => ConversionError,
+  ApiErr => ApiError,
+
+  fn path(_input: &Self::Input) -> Str {
+    "/v2/orders".into()
+  }
+
+  fn query(input: &Self::Input) -> Result<Option<Str>, Self::ConversionError> {
+    Ok(Some(to_query(input)?.into()))
+  }
+
+  fn parse(body: &[u8]) -> Result<Self::Output, Self::ConversionError> {
+    from_json::<Self::Output>(body).map_err(Self::ConversionError::from)
+  }
+
+  fn parse_err(body: &[u8]) -> Result<Self::ApiError, Vec<u8>> {
+    from_json::<Self::ApiError>(body).map_err(|_| body.to_vec())
+  }
+}
+
+
+/// A DELETE request to be made to the /v2/positions/{symbol} endpoint.
+///
+/// In contrast to `apca`'s `position::Delete`, this request supports
+/// closing only part of a position.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ClosePositionReq {
+  /// The symbol of the position to close.
+  #[serde(skip)]
+  pub symbol: asset::Symbol,
+  /// The quantity to close.
+  #[serde(rename = "qty", skip_serializing_if = "Option::is_none")]
+  pub quantity: Option<Num>,
+  /// The percentage of the position to close.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub percentage: Option<Num>,
+}
+
+
+EndpointDef! {
+  /// The representation of a DELETE request to the
+  /// /v2/positions/{symbol} endpoint.
+  pub ClosePosition(ClosePositionReq),
+  Ok => order::Order, [
+    /* 200 */ OK,
+  ],
+  Err => ClosePositionError, [
+    /// The position cannot be closed as requested, e.g., because the
+    /// quantity exceeds that of the position.
+    /* 403 */ FORBIDDEN => NotPermitted,
+    /// No position was found for the given symbol.
+    /* 404 */ NOT_FOUND => NotFound,
+    /// The provided quantity or percentage is invalid.
+    /* 422 */ UNPROCESSABLE_ENTITY => InvalidInput,
+  ],
+  ConversionErr => ConversionError,
+  ApiErr => ApiError,
+
+  fn method() -> Method {
+    Method::DELETE
+  }
+
+  fn path(input: &Self::Input) -> Str {
+    format!("/v2/positions/{}", input.symbol).into()
+  }
+
+  fn query(input: &Self::Input) -> Result<Option<Str>, Self::ConversionError> {
+    let query = to_query(input)?;
+    Ok((!query.is_empty()).then(|| query.into()))
+  }
+
+  fn parse(body: &[u8]) -> Result<Self::Output, Self::ConversionError> {
+    from_json::<Self::Output>(body).map_err(Self::ConversionError::from)
+  }
+
+  fn parse_err(body: &[u8]) -> Result<Self::ApiError, Vec<u8>> {
+    from_json::<Self::ApiError>(body).map_err(|_| body.to_vec())
+  }
+}
+
+
+/// A GET request to be made to the /v2/stocks/snapshots endpoint.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SnapshotsReq {
+  /// The symbols to retrieve snapshots for.
+  #[serde(serialize_with = "serialize_symbols")]
+  pub symbols: Vec<String>,
+  /// The data feed to use.
+  pub feed: Feed,
+}
+
+
+/// The latest trade in a symbol.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Trade {
+  /// The time stamp of the trade.
+  #[serde(rename(deserialize = "t"))]
+  pub time: DateTime<Utc>,
+  /// The price at which the trade happened.
+  #[serde(rename(deserialize = "p"))]
+  pub price: Num,
+  /// The size of the trade.
+  #[serde(rename(deserialize = "s"))]
+  pub size: u64,
+}
+
+
+/// The latest quote for a symbol.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Quote {
+  /// The time stamp of the quote.
+  #[serde(rename(deserialize = "t"))]
+  pub time: DateTime<Utc>,
+  /// The bid price.
+  #[serde(rename(deserialize = "bp"))]
+  pub bid_price: Num,
+  /// The bid size.
+  #[serde(rename(deserialize = "bs"))]
+  pub bid_size: u64,
+  /// The ask price.
+  #[serde(rename(deserialize = "ap"))]
+  pub ask_price: Num,
+  /// The ask size.
+  #[serde(rename(deserialize = "as"))]
+  pub ask_size: u64,
+}
+
+
+/// A daily aggregate bar.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Bar {
+  /// The beginning of the day the bar covers.
+  #[serde(rename(deserialize = "t"))]
+  pub time: DateTime<Utc>,
+  /// The open price.
+  #[serde(rename(deserialize = "o"))]
+  pub open: Num,
+  /// The highest price.
+  #[serde(rename(deserialize = "h"))]
+  pub high: Num,
+  /// The lowest price.
+  #[serde(rename(deserialize = "l"))]
+  pub low: Num,
+  /// The close price.
+  #[serde(rename(deserialize = "c"))]
+  pub close: Num,
+  /// The traded volume.
+  #[serde(rename(deserialize = "v"))]
+  pub volume: u64,
+}
+
+
+/// A snapshot of the market data for a symbol.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Snapshot {
+  /// The latest trade.
+  #[serde(rename(deserialize = "latestTrade"), default)]
+  pub latest_trade: Option<Trade>,
+  /// The latest quote.
+  #[serde(rename(deserialize = "latestQuote"), default)]
+  pub latest_quote: Option<Quote>,
+  /// The bar of the current (or most recent) trading day.
+  #[serde(rename(deserialize = "dailyBar"), default)]
+  pub daily_bar: Option<Bar>,
+  /// The bar of the trading day before that.
+  #[serde(rename(deserialize = "prevDailyBar"), default)]
+  pub prev_daily_bar: Option<Bar>,
+}
+
+
+EndpointDef! {
+  /// The representation of a GET request to the /v2/stocks/snapshots
+  /// endpoint.
+  pub Snapshots(SnapshotsReq),
+  Ok => BTreeMap<String, Snapshot>, [
+    /* 200 */ OK,
+  ],
+  Err => SnapshotsError, [
+    /// A provided symbol was invalid or the data feed is not supported.
+    /* 400 */ BAD_REQUEST => InvalidInput,
+    /// The data feed is not accessible with the current subscription.
+    /* 403 */ FORBIDDEN => NotPermitted,
+    /// The rate limit was exceeded, causing the request to be denied.
+    /* 429 */ TOO_MANY_REQUESTS => RateLimitExceeded,
+  ],
+  ConversionErr => ConversionError,
+  ApiErr => ApiError,
+
+  fn base_url() -> Option<Str> {
+    Some(DATA_BASE_URL.into())
+  }
+
+  fn path(_input: &Self::Input) -> Str {
+    "/v2/stocks/snapshots".into()
+  }
+
+  fn query(input: &Self::Input) -> Result<Option<Str>, Self::ConversionError> {
+    Ok(Some(to_query(input)?.into()))
+  }
+
+  fn parse(body: &[u8]) -> Result<Self::Output, Self::ConversionError> {
+    // Symbols without any data are reported as `null`. We just omit
+    // them.
+    from_json::<BTreeMap<String, Option<Snapshot>>>(body)
+      .map(|snapshots| {
+        snapshots
+          .into_iter()
+          .filter_map(|(symbol, snapshot)| Some((symbol, snapshot?)))
+          .collect()
+      })
+      .map_err(Self::ConversionError::from)
+  }
+
+  fn parse_err(body: &[u8]) -> Result<Self::ApiError, Vec<u8>> {
+    from_json::<Self::ApiError>(body).map_err(|_| body.to_vec())
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone as _;
+
+  use http_endpoint::Endpoint as _;
+
+
+  /// Check that we serialize `ListOrdersReq` objects into the expected
+  /// query strings.
+  #[test]
+  fn serialize_list_request() {
+    let request = ListOrdersReq::default();
+    let query = to_query(&request).unwrap();
+    assert_eq!(query, "status=open&direction=desc&nested=true");
+
+    let request = ListOrdersReq {
+      status: orders::Status::All,
+      limit: Some(500),
+      after: Some(Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap()),
+      direction: Direction::Ascending,
+      nested: false,
+      symbols: vec!["SPY".to_string(), "AAPL".to_string()],
+      side: Some(order::Side::Sell),
+      ..Default::default()
+    };
+    let query = to_query(&request).unwrap();
+    let expected = "status=all&limit=500&after=2024-01-02T03%3A04%3A05Z&direction=asc\
+                    &nested=false&symbols=SPY%2CAAPL&side=sell";
+    assert_eq!(query, expected);
+  }
+
+  /// Check that we serialize `ClosePositionReq` objects into the
+  /// expected query strings.
+  #[test]
+  fn serialize_close_position_request() {
+    let mut request = ClosePositionReq {
+      symbol: asset::Symbol::Sym("SPY".to_string()),
+      quantity: None,
+      percentage: None,
+    };
+    assert_eq!(to_query(&request).unwrap(), "");
+
+    request.quantity = Some(Num::new(5, 2));
+    assert_eq!(to_query(&request).unwrap(), "qty=2.5");
+
+    request.quantity = None;
+    request.percentage = Some(Num::from(50));
+    assert_eq!(to_query(&request).unwrap(), "percentage=50");
+  }
+
+  /// Check that we can parse a response of the /v2/stocks/snapshots
+  /// endpoint.
+  #[test]
+  fn parse_snapshots() {
+    let request = SnapshotsReq {
+      symbols: vec!["SPY".to_string(), "XXXX".to_string()],
+      feed: Feed::IEX,
+    };
+    assert_eq!(to_query(&request).unwrap(), "symbols=SPY%2CXXXX&feed=iex");
+
+    let response = br#"{
+  "SPY": {
+    "latestTrade": {"t": "2024-01-02T20:59:59.5Z", "x": "V", "p": 472.65, "s": 100, "c": ["@"], "i": 1, "z": "B"},
+    "latestQuote": {"t": "2024-01-02T20:59:59.9Z", "ax": "V", "ap": 472.7, "as": 2, "bx": "V", "bp": 472.6, "bs": 3, "c": ["R"], "z": "B"},
+    "minuteBar": {"t": "2024-01-02T20:59:00Z", "o": 472.5, "h": 472.8, "l": 472.4, "c": 472.65, "v": 9876, "n": 120, "vw": 472.6},
+    "dailyBar": {"t": "2024-01-02T05:00:00Z", "o": 475.1, "h": 476.2, "l": 470.5, "c": 472.65, "v": 1234567, "n": 23456, "vw": 473.1},
+    "prevDailyBar": {"t": "2023-12-29T05:00:00Z", "o": 476.5, "h": 477.0, "l": 473.3, "c": 475.31, "v": 987654, "n": 12345, "vw": 475.5}
+  },
+  "XXXX": null
+}"#;
+    let snapshots = Snapshots::parse(response).unwrap();
+    assert_eq!(snapshots.len(), 1);
+    let spy = &snapshots["SPY"];
+    let trade = spy.latest_trade.as_ref().unwrap();
+    assert_eq!(trade.price, Num::new(47265, 100));
+    assert_eq!(trade.size, 100);
+    let quote = spy.latest_quote.as_ref().unwrap();
+    assert_eq!(quote.bid_price, Num::new(4726, 10));
+    assert_eq!(quote.ask_size, 2);
+    let prev = spy.prev_daily_bar.as_ref().unwrap();
+    assert_eq!(prev.close, Num::new(47531, 100));
+  }
+}
@@ -8,29 +8,51 @@
   clippy::let_unit_value
 )]
 
+mod alert;
 mod args;
+mod config;
+mod endpoint;
+mod rebalance;
 
 use std::borrow::Cow;
+use std::cell::Cell;
 use std::cmp::max;
+use std::cmp::min;
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::env::args_os;
 use std::env::current_exe;
 use std::env::split_paths;
 use std::env::var_os;
+use std::error::Error as StdError;
 use std::ffi::OsStr;
 use std::ffi::OsString;
 use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
 use std::fs::read_dir;
+use std::fs::read_to_string;
+use std::fs::File;
 use std::future::Future;
 use std::io;
+use std::io::stdin;
+use std::io::BufRead as _;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::IsTerminal as _;
+use std::io::LineWriter;
 use std::io::Write;
 use std::mem::take;
+use std::ops::ControlFlow;
 use std::ops::Deref as _;
 use std::os::unix::process::CommandExt as _;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command as Process;
 use std::process::ExitCode;
+use std::time::Duration as StdDuration;
 
 use apca::api::v2::account;
 use apca::api::v2::account_activities;
@@ -50,6 +72,8 @@ use apca::data::v2::last_quotes;
 use apca::data::v2::stream;
 use apca::ApiInfo;
 use apca::Client;
+use apca::RequestError;
+use apca::Subscribable;
 
 use anyhow::anyhow;
 use anyhow::bail;
@@ -61,20 +85,21 @@ use anyhow::Result;
 use chrono::offset::Local;
 use chrono::offset::Utc;
 use chrono::DateTime;
-use chrono::Datelike as _;
+use chrono::Duration;
+use chrono::NaiveDate;
 use chrono::NaiveDateTime;
 use chrono::TimeZone;
-use chrono::Timelike as _;
 use chrono_tz::America::New_York;
 
 use clap::CommandFactory as _;
 use clap::Parser as _;
 
-use futures::future::join;
+use futures::future::pending;
 use futures::future::ready;
 use futures::future::FutureExt as _;
 use futures::future::TryFutureExt;
 use futures::join;
+use futures::stream::iter;
 use futures::stream::FuturesOrdered;
 use futures::stream::FuturesUnordered;
 use futures::stream::StreamExt;
@@ -82,7 +107,19 @@ use futures::stream::TryStreamExt;
 
 use num_decimal::Num;
 
+use serde::Deserialize;
+use serde::Serialize;
+use serde::Serializer;
+use serde_json::from_str as from_json;
+use serde_json::to_string as to_json;
+use serde_json::to_string_pretty as to_json_pretty;
+use serde_json::Error as JsonError;
+use serde_json::Value as JsonValue;
+
 use tokio::runtime::Builder;
+use tokio::time::sleep;
+use tokio::time::timeout_at;
+use tokio::time::Instant;
 
 use tracing::subscriber::set_global_default as set_global_subscriber;
 use tracing::warn;
@@ -91,32 +128,52 @@ use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::fmt::time::ChronoLocal;
 use tracing_subscriber::FmtSubscriber;
 
+use uuid::Uuid;
+
 use yansi::Painted;
 
 use crate::args::Account;
 use crate::args::Activity;
 use crate::args::ActivityGet;
+use crate::args::AddAlert;
+use crate::args::Alert;
 use crate::args::Args;
 use crate::args::Asset;
 use crate::args::Bars;
-use crate::args::CancelOrder;
+use crate::args::BarsFormat;
+use crate::args::BatchFormat;
+use crate::args::CancelOrders;
 use crate::args::ChangeOrder;
 use crate::args::Command;
 use crate::args::Config;
 use crate::args::ConfigSet;
 use crate::args::CreateWatchlist;
 use crate::args::DataSource;
+use crate::args::Direction;
+use crate::args::GetBars;
+use crate::args::ListOrders;
 use crate::args::Order;
-use crate::args::OrderId;
+use crate::args::Output;
+use crate::args::Portfolio;
 use crate::args::Position;
+use crate::args::Quote;
+use crate::args::Rebalance;
+use crate::args::Risk;
+use crate::args::Rounding;
 use crate::args::Side;
+use crate::args::SubmitBatch;
 use crate::args::SubmitOrder;
 use crate::args::Symbol;
 use crate::args::TimeFrame;
+use crate::args::TimeInForce;
 use crate::args::UpdateWatchlist;
 use crate::args::Updates;
 use crate::args::Watchlist;
 use crate::args::WatchlistId;
+use crate::rebalance::round_quantity;
+use crate::rebalance::Adjustment;
+use crate::rebalance::Holding;
+use crate::rebalance::Targets;
 
 
 /// The string type we use on many occasions.
@@ -126,6 +183,11 @@ type Str = Cow<'static, str>;
 const EXT_PREFIX: &str = "apcacli-";
 /// The maximum concurrency to use when issuing requests.
 const MAX_CONCURRENCY: usize = 32;
+/// The maximum number of orders Alpaca reports in response to a single
+/// request.
+const MAX_ORDERS_PER_PAGE: usize = 500;
+/// The maximum length of a client order ID, as documented by Alpaca.
+const MAX_CLIENT_ORDER_ID_LEN: usize = 48;
 
 
 // A replacement of the standard println!() macro that does not panic
@@ -141,6 +203,30 @@ macro_rules! println {
 }
 
 
+/// Print a value as a pretty-printed JSON document.
+fn print_json<T>(value: &T) -> Result<()>
+where
+  T: Serialize + ?Sized,
+{
+  let json = to_json_pretty(value).context("failed to serialize output as JSON")?;
+  println!("{json}");
+  Ok(())
+}
+
+/// Print a value as JSON on a single line.
+///
+/// This format is used for streamed output, where each line contains
+/// a self-contained JSON document.
+fn print_json_line<T>(value: &T) -> Result<()>
+where
+  T: Serialize + ?Sized,
+{
+  let json = to_json(value).context("failed to serialize output as JSON")?;
+  println!("{json}");
+  Ok(())
+}
+
+
 /// Format an account status.
 fn format_account_status(status: account::Status) -> String {
   match status {
@@ -161,21 +247,25 @@ fn format_account_status(status: account::Status) -> String {
 
 
 /// The handler for the 'account' command.
-async fn account(client: Client, account: Account) -> Result<()> {
+async fn account(client: Client, account: Account, output: Output) -> Result<()> {
   match account {
-    Account::Get => account_get(client).await,
-    Account::Activity(activity) => account_activity(client, activity).await,
-    Account::Config(config) => account_config(client, config).await,
+    Account::Get => account_get(client, output).await,
+    Account::Activity(activity) => account_activity(client, activity, output).await,
+    Account::Config(config) => account_config(client, config, output).await,
   }
 }
 
 /// Print information about the account.
-async fn account_get(client: Client) -> Result<()> {
+async fn account_get(client: Client, output: Output) -> Result<()> {
   let account = client
     .issue::<account::Get>(&())
     .await
     .with_context(|| "failed to retrieve account information")?;
 
+  if output == Output::Json {
+    return print_json(&account)
+  }
+
   println!(
     r#"account:
   id:                 {id}
@@ -220,9 +310,9 @@ async fn account_get(client: Client) -> Result<()> {
 
 
 /// The handler for the 'account activity' command.
-async fn account_activity(client: Client, activity: Activity) -> Result<()> {
+async fn account_activity(client: Client, activity: Activity, output: Output) -> Result<()> {
   match activity {
-    Activity::Get(get) => account_activity_get(client, get).await,
+    Activity::Get(get) => account_activity_get(client, get, output).await,
   }
 }
 
@@ -305,8 +395,55 @@ fn sort_account_activity(activities: &mut [account_activities::Activity]) {
 }
 
 
+/// The JSON representation of an account activity.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum JsonActivity<'a> {
+  Trade {
+    id: &'a str,
+    time: DateTime<Utc>,
+    side: &'static str,
+    symbol: &'a str,
+    quantity: &'a Num,
+    price: &'a Num,
+    order_id: order::Id,
+  },
+  NonTrade {
+    id: &'a str,
+    date: DateTime<Utc>,
+    #[serde(rename = "type")]
+    type_: account_activities::ActivityType,
+    symbol: Option<&'a str>,
+    amount: &'a Num,
+  },
+}
+
+impl<'a> From<&'a account_activities::Activity> for JsonActivity<'a> {
+  fn from(activity: &'a account_activities::Activity) -> Self {
+    match activity {
+      account_activities::Activity::Trade(trade) => Self::Trade {
+        id: &trade.id,
+        time: trade.transaction_time,
+        side: format_activity_side(trade.side),
+        symbol: &trade.symbol,
+        quantity: &trade.quantity,
+        price: &trade.price,
+        order_id: trade.order_id,
+      },
+      account_activities::Activity::NonTrade(non_trade) => Self::NonTrade {
+        id: &non_trade.id,
+        date: non_trade.date,
+        type_: non_trade.type_,
+        symbol: non_trade.symbol.as_deref(),
+        amount: &non_trade.net_amount,
+      },
+    }
+  }
+}
+
+
 /// Retrieve account activity.
-async fn account_activity_get(client: Client, get: ActivityGet) -> Result<()> {
+async fn account_activity_get(client: Client, get: ActivityGet, output: Output) -> Result<()> {
   let request = account_activities::ActivityReq {
     after: get
       .begin
@@ -324,6 +461,14 @@ async fn account_activity_get(client: Client, get: ActivityGet) -> Result<()> {
   let mut activities = activity.with_context(|| "failed to retrieve account activity")?;
   sort_account_activity(&mut activities);
 
+  if output == Output::Json {
+    let activities = activities
+      .iter()
+      .map(JsonActivity::from)
+      .collect::<Vec<_>>();
+    return print_json(&activities)
+  }
+
   for activity in activities {
     match activity {
       account_activities::Activity::Trade(trade) => {
@@ -352,9 +497,9 @@ async fn account_activity_get(client: Client, get: ActivityGet) -> Result<()> {
 
 
 /// Retrieve or modify the account configuration.
-async fn account_config(client: Client, config: Config) -> Result<()> {
+async fn account_config(client: Client, config: Config, output: Output) -> Result<()> {
   match config {
-    Config::Get => account_config_get(client).await,
+    Config::Get => account_config_get(client, output).await,
     Config::Set(set) => account_config_set(client, set).await,
   }
 }
@@ -373,12 +518,16 @@ fn format_trade_confirmation(confirmation: account_config::TradeConfirmation) ->
 }
 
 /// Retrieve the account configuration.
-async fn account_config_get(client: Client) -> Result<()> {
+async fn account_config_get(client: Client, output: Output) -> Result<()> {
   let config = client
     .issue::<account_config::Get>(&())
     .await
     .with_context(|| "failed to retrieve account configuration")?;
 
+  if output == Output::Json {
+    return print_json(&config)
+  }
+
   println!(
     r#"account configuration:
   trade confirmation:  {trade_confirmation}
@@ -437,20 +586,24 @@ async fn account_config_set(client: Client, set: ConfigSet) -> Result<()> {
 
 
 /// The handler for the 'asset' command.
-async fn asset(client: Client, asset: Asset) -> Result<()> {
+async fn asset(client: Client, asset: Asset, output: Output) -> Result<()> {
   match asset {
-    Asset::Get { symbol } => asset_get(client, symbol).await,
-    Asset::List { class } => asset_list(client, class).await,
+    Asset::Get { symbol } => asset_get(client, symbol, output).await,
+    Asset::List { class } => asset_list(client, class, output).await,
   }
 }
 
 /// Print information about the asset with the given symbol.
-async fn asset_get(client: Client, symbol: Symbol) -> Result<()> {
+async fn asset_get(client: Client, symbol: Symbol, output: Output) -> Result<()> {
   let asset = client
     .issue::<asset::Get>(&symbol.0)
     .await
     .with_context(|| format!("failed to retrieve asset information for {}", symbol.0))?;
 
+  if output == Output::Json {
+    return print_json(&asset)
+  }
+
   println!(
     r#"{sym}:
   id:              {id}
@@ -475,7 +628,7 @@ async fn asset_get(client: Client, symbol: Symbol) -> Result<()> {
 }
 
 /// Print all tradeable assets.
-async fn asset_list(client: Client, class: asset::Class) -> Result<()> {
+async fn asset_list(client: Client, class: asset::Class, output: Output) -> Result<()> {
   let request = assets::ListReq {
     class,
     ..Default::default()
@@ -488,6 +641,14 @@ async fn asset_list(client: Client, class: asset::Class) -> Result<()> {
 
   assets.sort_by(|x, y| x.symbol.cmp(&y.symbol));
 
+  if output == Output::Json {
+    let assets = assets
+      .iter()
+      .filter(|asset| asset.tradable)
+      .collect::<Vec<_>>();
+    return print_json(&assets)
+  }
+
   let sym_max = max_width(&assets, |a| a.symbol.len());
 
   for asset in assets.into_iter().filter(|asset| asset.tradable) {
@@ -502,92 +663,178 @@ async fn asset_list(client: Client, class: asset::Class) -> Result<()> {
 }
 
 
+/// Interpret a date/time as being in the America/New_York time zone and
+/// convert it to UTC.
+fn new_york_to_utc(time: &NaiveDateTime) -> Option<DateTime<Utc>> {
+  New_York
+    .from_local_datetime(time)
+    .single()
+    .map(|time| time.with_timezone(&Utc))
+}
+
+
 /// The handler for the 'bars' command.
-async fn bars(client: Client, bars: Bars) -> Result<()> {
+async fn bars(client: Client, bars: Bars, output: Output) -> Result<()> {
   match bars {
-    Bars::Get {
-      symbol,
-      time_frame,
-      start,
-      end,
-    } => bars_get(client, symbol, time_frame, start, end).await,
+    Bars::Get(get) => bars_get(client, get, output).await,
+  }
+}
+
+/// The JSON representation of a historical aggregate bar.
+#[derive(Debug, Serialize)]
+struct JsonBar<'b> {
+  time: DateTime<Utc>,
+  open: &'b Num,
+  high: &'b Num,
+  low: &'b Num,
+  close: &'b Num,
+  volume: usize,
+  vwap: &'b Num,
+}
+
+impl<'b> From<&'b bars::Bar> for JsonBar<'b> {
+  fn from(bar: &'b bars::Bar) -> Self {
+    Self {
+      time: bar.time,
+      open: &bar.open,
+      high: &bar.high,
+      low: &bar.low,
+      close: &bar.close,
+      volume: bar.volume,
+      vwap: &bar.weighted_average,
+    }
+  }
+}
+
+/// The header of the CSV representation of historical aggregate bars.
+const BARS_CSV_HEADER: &str = "time,open,high,low,close,volume,vwap";
+
+/// Write the header for bars in the given format, if any.
+fn write_bars_header<W>(writer: &mut W, format: BarsFormat) -> io::Result<()>
+where
+  W: Write + ?Sized,
+{
+  match format {
+    BarsFormat::Text | BarsFormat::Ndjson => Ok(()),
+    BarsFormat::Csv => writeln!(writer, "{BARS_CSV_HEADER}"),
+  }
+}
+
+/// Write a single historical aggregate bar in the given format.
+fn write_bar<W>(writer: &mut W, format: BarsFormat, bar: &bars::Bar) -> io::Result<()>
+where
+  W: Write + ?Sized,
+{
+  match format {
+    BarsFormat::Text => {
+      let time = New_York.from_utc_datetime(&bar.time.naive_utc());
+      writeln!(
+        writer,
+        r#"{timestamp}:
+  open price:    {open_price}
+  close price:   {close_price}
+  high price:    {high_price}
+  low price:     {low_price}
+  volume:        {volume}
+"#,
+        timestamp = format_date_time(time),
+        open_price = bar.open,
+        close_price = bar.close,
+        high_price = bar.high,
+        low_price = bar.low,
+        volume = bar.volume,
+      )
+    },
+    BarsFormat::Csv => writeln!(
+      writer,
+      "{time},{open},{high},{low},{close},{volume},{vwap}",
+      time = bar.time.to_rfc3339(),
+      open = bar.open,
+      high = bar.high,
+      low = bar.low,
+      close = bar.close,
+      volume = bar.volume,
+      vwap = bar.weighted_average,
+    ),
+    BarsFormat::Ndjson => {
+      let json = to_json(&JsonBar::from(bar)).map_err(io::Error::from)?;
+      writeln!(writer, "{json}")
+    },
   }
 }
 
 /// Retrieve and print historical aggregate bars for an asset.
-async fn bars_get(
-  client: Client,
-  symbol: String,
-  time_frame: TimeFrame,
-  start: NaiveDateTime,
-  end: NaiveDateTime,
-) -> Result<()> {
+///
+/// Bars are written out as they are received, while paging through
+/// the result set.
+async fn bars_get(client: Client, get: GetBars, output: Output) -> Result<()> {
+  let GetBars {
+    symbol,
+    time_frame,
+    start,
+    end,
+    format,
+    output_file,
+  } = get;
+
+  let format = format.unwrap_or(match output {
+    Output::Text => BarsFormat::Text,
+    Output::Json => BarsFormat::Ndjson,
+  });
+  let mut writer = match &output_file {
+    Some(path) => {
+      let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+      Box::new(BufWriter::new(file)) as Box<dyn Write>
+    },
+    None => Box::new(io::stdout()) as Box<dyn Write>,
+  };
+
   let time_frame = match time_frame {
     TimeFrame::Day => bars::TimeFrame::OneDay,
     TimeFrame::Hour => bars::TimeFrame::OneHour,
     TimeFrame::Minute => bars::TimeFrame::OneMinute,
   };
 
-  let start = New_York
-    .with_ymd_and_hms(
-      start.year(),
-      start.month(),
-      start.day(),
-      start.hour(),
-      start.minute(),
-      start.second(),
-    )
-    .single()
-    .ok_or_else(|| anyhow!("cannot work with invalid/ambiguous start time"))?
-    .with_timezone(&Utc);
-  let end = New_York
-    .with_ymd_and_hms(
-      end.year(),
-      end.month(),
-      end.day(),
-      end.hour(),
-      end.minute(),
-      end.second(),
-    )
-    .single()
-    .ok_or_else(|| anyhow!("cannot work with invalid/ambiguous end time"))?
-    .with_timezone(&Utc);
+  let start = new_york_to_utc(&start)
+    .ok_or_else(|| anyhow!("cannot work with invalid/ambiguous start time"))?;
+  let end =
+    new_york_to_utc(&end).ok_or_else(|| anyhow!("cannot work with invalid/ambiguous end time"))?;
   let mut request = bars::ListReqInit {
     adjustment: Some(bars::Adjustment::All),
     ..Default::default()
   }
   .init(symbol.clone(), start, end, time_frame);
 
+  // Similar to our `println` replacement, we silently stop producing
+  // output once the reading end of a pipe went away.
+  match write_bars_header(&mut writer, format) {
+    Ok(()) => (),
+    Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+    Err(err) => return Err(Error::new(err).context("failed to write bars")),
+  }
+
   loop {
     let response = client
       .issue::<bars::List>(&request)
       .await
       .with_context(|| format!("failed to retrieve historical aggregate bars for {symbol}"))?;
     for bar in response.bars {
-      let time = New_York.from_utc_datetime(&bar.time.naive_utc());
-      println!(
-        r#"{timestamp}:
-  open price:    {open_price}
-  close price:   {close_price}
-  high price:    {high_price}
-  low price:     {low_price}
-  volume:        {volume}
-"#,
-        timestamp = format_date_time(time),
-        open_price = bar.open,
-        close_price = bar.close,
-        high_price = bar.high,
-        low_price = bar.low,
-        volume = bar.volume,
-      );
+      match write_bar(&mut writer, format, &bar) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+        Err(err) => return Err(Error::new(err).context("failed to write bars")),
+      }
     }
 
     if response.next_page_token.is_none() {
-      break Ok(())
+      break
     }
 
     request.page_token = response.next_page_token;
   }
+
+  writer.flush().context("failed to write bars")
 }
 
 
@@ -723,241 +970,1711 @@ fn format_time_in_force_short(time_in_force: order::TimeInForce) -> &'static str
 }
 
 
-async fn stream_trade_updates(client: Client) -> Result<()> {
-  let currency = client
-    .issue::<account::Get>(&())
-    .await
-    .context("failed to retrieve account information")?
-    .currency;
-
-  let (stream, _subscription) = client
-    .subscribe::<updates::OrderUpdates>()
-    .await
-    .with_context(|| "failed to subscribe to trade updates")?;
+/// The delay before the first attempt of reconnecting a dropped
+/// stream.
+const RECONNECT_DELAY_MIN: StdDuration = StdDuration::from_secs(1);
+/// The maximum delay between two attempts of reconnecting a dropped
+/// stream.
+const RECONNECT_DELAY_MAX: StdDuration = StdDuration::from_secs(60);
 
-  stream
-    .try_for_each(|result| async {
-      let update = result.unwrap();
-      println!(
-        r#"{symbol} {status}:
-  order id:       {id}
-  status:         {order_status}
-  type:           {type_}
-  side:           {side}
-  time-in-force:  {time_in_force}
-  {amount_type:15} {amount}
-  filled:         {filled}
-"#,
-        symbol = update.order.symbol,
-        status = format_trade_status(update.event),
-        id = update.order.id.as_hyphenated(),
-        order_status = format_order_status(update.order.status),
-        type_ = format_order_type(update.order.type_),
-        side = format_order_side(update.order.side),
-        time_in_force = format_time_in_force(update.order.time_in_force),
-        amount_type = format_amount_type(&update.order.amount).to_string() + ":",
-        amount = format_amount(&update.order.amount, &currency),
-        filled = update.order.filled_quantity,
-      );
-      Ok(())
-    })
-    .await?;
 
-  Ok(())
+/// Exponentially increasing delays between reconnection attempts.
+#[derive(Debug)]
+struct Backoff {
+  delay: StdDuration,
 }
 
+impl Backoff {
+  /// Retrieve the delay to wait before the next attempt, doubling the
+  /// one after that (up to `RECONNECT_DELAY_MAX`).
+  fn next_delay(&mut self) -> StdDuration {
+    let delay = self.delay;
+    self.delay = min(delay * 2, RECONNECT_DELAY_MAX);
+    delay
+  }
 
-/// Subscribe to and stream realtime market data updates.
-async fn stream_realtime_data(
-  client: Client,
-  source: DataSource,
-  symbols: Vec<String>,
-) -> Result<()> {
-  let result = match source {
-    DataSource::Iex => {
-      client
-        .subscribe::<stream::RealtimeData<stream::IEX>>()
-        .await
-    },
-    DataSource::Sip => {
-      client
-        .subscribe::<stream::RealtimeData<stream::SIP>>()
-        .await
-    },
-  };
+  /// Reset the delay after a successful reconnection.
+  fn reset(&mut self) {
+    self.delay = RECONNECT_DELAY_MIN
+  }
+}
 
-  let (mut stream, mut subscription) =
-    result.with_context(|| "failed to subscribe to realtime market data updates")?;
+impl Default for Backoff {
+  fn default() -> Self {
+    Self {
+      delay: RECONNECT_DELAY_MIN,
+    }
+  }
+}
 
-  let mut data = stream::MarketData::default();
-  data.set_bars(symbols);
 
-  let subscribe = subscription.subscribe(&data).boxed_local().fuse();
-  let () = stream::drive(subscribe, &mut stream)
-    .await
-    .map_err(|result| {
-      result
-        .map(|result| apca::Error::Json(result.unwrap_err()))
-        .map_err(apca::Error::WebSocket)
-        .unwrap_or_else(|err| err)
-    })
-    .context("failed to subscribe to market data")???;
-
-  stream
-    .try_for_each(|result| async {
-      let data = result.unwrap();
-      match data {
-        stream::Data::Bar(bar) => {
-          println!(
-            r#"{symbol}:
-  time stamp:    {timestamp}
-  open price:    {open_price}
-  close price:   {close_price}
-  high price:    {high_price}
-  low price:     {low_price}
-  volume:        {volume}"#,
-            symbol = bar.symbol,
-            timestamp = format_local_time_short(bar.timestamp),
-            open_price = bar.open_price,
-            close_price = bar.close_price,
-            high_price = bar.high_price,
-            low_price = bar.low_price,
-            volume = bar.volume,
-          );
-        },
-        _ => warn!("received unexpected stream element: {:?}", data),
-      }
-      Ok(())
-    })
-    .await?;
+/// Wait before the next reconnection attempt, logging why we have to
+/// reconnect.
+async fn wait_for_reconnect(what: &str, error: Option<Error>, backoff: &mut Backoff) {
+  let delay = backoff.next_delay();
+  match error {
+    Some(error) => warn!(
+      "{what} stream disconnected: {error:#}; reconnecting in {}s",
+      delay.as_secs()
+    ),
+    None => warn!("{what} stream ended; reconnecting in {}s", delay.as_secs()),
+  }
+  let () = sleep(delay).await;
+}
 
-  Ok(())
+
+/// Log the gap in updates caused by a reconnection.
+fn log_gap(what: &str, last_seen: DateTime<Utc>) {
+  let gap = Utc::now() - last_seen;
+  warn!(
+    "reconnected to {what} stream; updates since {} ({}s ago) may have been missed",
+    format_local_time_short(last_seen),
+    gap.num_seconds(),
+  );
 }
 
-async fn updates(client: Client, updates: Updates) -> Result<()> {
-  match updates {
-    Updates::Trades => stream_trade_updates(client).await,
-    Updates::Data { source, symbols } => stream_realtime_data(client, source, symbols).await,
-  }
+
+/// Map the status of an order to the trade update event reporting a
+/// transition into it.
+fn order_status_event(status: order::Status) -> Option<updates::OrderStatus> {
+  let event = match status {
+    order::Status::New
+    | order::Status::Accepted
+    | order::Status::AcceptedForBidding
+    | order::Status::Held => updates::OrderStatus::New,
+    order::Status::Replaced => updates::OrderStatus::Replaced,
+    order::Status::PartiallyFilled => updates::OrderStatus::PartialFill,
+    order::Status::Filled => updates::OrderStatus::Filled,
+    order::Status::DoneForDay => updates::OrderStatus::DoneForDay,
+    order::Status::Canceled => updates::OrderStatus::Canceled,
+    order::Status::Expired => updates::OrderStatus::Expired,
+    order::Status::PendingNew => updates::OrderStatus::PendingNew,
+    order::Status::PendingCancel => updates::OrderStatus::PendingCancel,
+    order::Status::PendingReplace => updates::OrderStatus::PendingReplace,
+    order::Status::Stopped => updates::OrderStatus::Stopped,
+    order::Status::Rejected => updates::OrderStatus::Rejected,
+    order::Status::Suspended => updates::OrderStatus::Suspended,
+    order::Status::Calculated => updates::OrderStatus::Calculated,
+    _ => return None,
+  };
+  Some(event)
 }
 
-/// Print the current market status.
-async fn market(client: Client) -> Result<()> {
-  let clock = client
-    .issue::<clock::Get>(&())
+
+/// Determine the time before which none of the currently open orders
+/// was submitted.
+///
+/// Any order that gets updated from now on was submitted at or after
+/// this time, which bounds how far back we have to look for missed
+/// updates.
+async fn order_horizon(client: &Client) -> Result<DateTime<Utc>> {
+  let now = Utc::now();
+  let request = endpoint::ListOrdersReq {
+    status: orders::Status::Open,
+    nested: false,
+    ..Default::default()
+  };
+  let orders = list_orders(client, request, None)
     .await
-    .with_context(|| "failed to retrieve market clock")?;
+    .context("failed to retrieve open orders")?;
 
-  println!(
-    r#"market:
-  open:         {open}
-  current time: {current}
-  next open:    {next_open}
-  next close:   {next_close}"#,
-    open = clock.open,
-    current = format_local_time(clock.current),
-    next_open = format_local_time(clock.next_open),
-    next_close = format_local_time(clock.next_close),
-  );
-  Ok(())
+  let horizon = orders.iter().map(submission_time).fold(now, min);
+  Ok(horizon)
 }
 
 
-/// Convert a certain monetary value into the maximum number of shares
-/// purchasable (i.e., a quantity).
-async fn value_to_quantity(
+/// Convert the orders updated since the given time into trade updates
+/// reflecting their current state, ordered by update time.
+fn order_updates_since(
+  orders: Vec<order::Order>,
+  since: DateTime<Utc>,
+) -> Vec<updates::OrderUpdate> {
+  let mut updates = orders
+    .into_iter()
+    .filter(|order| order.updated_at.is_some_and(|updated| updated >= since))
+    .filter_map(|order| {
+      let event = order_status_event(order.status)?;
+      Some(updates::OrderUpdate { event, order })
+    })
+    .collect::<Vec<_>>();
+  updates.sort_by_key(|update| update.order.updated_at);
+  updates
+}
+
+
+/// Retrieve the orders updated since the given time, in the form of
+/// trade updates reflecting their current state.
+///
+/// `horizon` is the submission time of the oldest order that may have
+/// been updated in the meantime, as reported by `order_horizon`. If it
+/// is not known, only the most recently submitted orders are checked.
+async fn missed_trade_updates(
   client: &Client,
-  symbol: &str,
-  side: order::Side,
-  value: &Num,
-  price: Option<Num>,
-) -> Result<Num> {
-  let price = match price {
-    Some(price) => price,
-    None => {
-      let request = last_quotes::GetReqInit::default().init([symbol]);
-      let mut quotes = client
-        .issue::<last_quotes::Get>(&request)
-        .await
-        .with_context(|| format!("failed to retrieve last quote for {symbol}"))?;
+  since: DateTime<Utc>,
+  horizon: Option<DateTime<Utc>>,
+) -> Result<Vec<updates::OrderUpdate>> {
+  let request = endpoint::ListOrdersReq {
+    status: orders::Status::All,
+    // Time bounds are exclusive.
+    after: horizon.map(|horizon| horizon - Duration::microseconds(1)),
+    // The trade update stream reports legs individually.
+    nested: false,
+    ..Default::default()
+  };
+  let limit = horizon.is_none().then_some(MAX_ORDERS_PER_PAGE);
+  let orders = list_orders(client, request, limit)
+    .await
+    .context("failed to retrieve recent orders")?;
 
-      let quote = match quotes.as_mut_slice() {
-        [(_symbol, quote)] => {
-          debug_assert_eq!(_symbol, symbol);
-          quote
-        },
-        _ => bail!(
-          "received unexpected number of quotes from Alpaca ({})",
-          quotes.len()
-        ),
-      };
+  Ok(order_updates_since(orders, since))
+}
 
-      let price = match side {
-        order::Side::Buy => &mut quote.ask_price,
-        order::Side::Sell => &mut quote.bid_price,
-      };
 
-      ensure!(
-        !price.is_zero(),
-        "most recent quote for {} contains price of zero; unable to estimate quantity",
-        symbol
-      );
-      take(price)
-    },
-  };
+/// A market data update as received, before being parsed into the
+/// respective `apca` types.
+type RawData = stream::Data<JsonValue, JsonValue, JsonValue>;
 
-  Ok(value / price)
+
+/// A stream message as recorded via `--record`.
+///
+/// Market data messages are recorded verbatim and tagged the same way
+/// Alpaca tags them. Trade updates are recorded as parsed by `apca`, as
+/// it does not provide access to them in any other form. Messages that
+/// could not be parsed at all are recorded along with the error.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "T")]
+enum RecordedMessage {
+  /// A minute aggregate bar.
+  #[serde(rename = "b")]
+  Bar(JsonValue),
+  /// A quote.
+  #[serde(rename = "q")]
+  Quote(JsonValue),
+  /// A trade.
+  #[serde(rename = "t")]
+  Trade(JsonValue),
+  /// An update to one of the account's orders.
+  #[serde(rename = "trade_update")]
+  TradeUpdate(updates::OrderUpdate),
+  /// A message that failed to parse.
+  #[serde(rename = "unparsable")]
+  Unparsable { error: String },
 }
 
+impl RecordedMessage {
+  /// Create a `RecordedMessage` from a raw market data update,
+  /// handing back updates that can't be represented.
+  fn from_data(data: RawData) -> Result<Self, RawData> {
+    let message = match data {
+      stream::Data::Bar(bar) => Self::Bar(bar),
+      stream::Data::Quote(quote) => Self::Quote(quote),
+      stream::Data::Trade(trade) => Self::Trade(trade),
+      data => return Err(data),
+    };
+    Ok(message)
+  }
 
-/// The handler for the 'order' command.
-async fn order(client: Client, order: Order) -> Result<()> {
-  match order {
-    Order::Submit(submit) => order_submit(client, submit).await,
-    Order::Change(change) => order_change(client, change).await,
-    Order::Cancel { cancel } => order_cancel(client, cancel).await,
-    Order::Get { id } => order_get(client, id).await,
-    Order::List { closed } => order_list(client, closed).await,
+  /// Parse the message into a market data update, if it is one.
+  fn to_data(&self) -> Option<Result<stream::Data, JsonError>> {
+    let result = match self {
+      Self::Bar(bar) => stream::Bar::deserialize(bar).map(stream::Data::Bar),
+      Self::Quote(quote) => stream::Quote::deserialize(quote).map(stream::Data::Quote),
+      Self::Trade(trade) => stream::Trade::deserialize(trade).map(stream::Data::Trade),
+      Self::TradeUpdate(..) | Self::Unparsable { .. } => return None,
+    };
+    Some(result)
   }
 }
 
 
-/// Determine the type of an order by looking at the limit and stop
-/// prices, if any.
-fn determine_order_type(limit_price: &Option<Num>, stop_price: &Option<Num>) -> order::Type {
-  match (limit_price.is_some(), stop_price.is_some()) {
-    (true, true) => order::Type::StopLimit,
-    (true, false) => order::Type::Limit,
-    (false, true) => order::Type::Stop,
-    (false, false) => order::Type::Market,
-  }
+/// A recorded stream message along with the time it was received.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Record {
+  time: DateTime<Utc>,
+  message: RecordedMessage,
 }
 
 
-/// Submit an order.
-async fn order_submit(client: Client, submit: SubmitOrder) -> Result<()> {
-  let SubmitOrder {
-    side,
-    symbol,
-    quantity,
-    value,
-    limit_price,
-    stop_price,
-    take_profit_price,
-    stop_loss_stop_price,
-    stop_loss_limit_price,
-    extended_hours,
-    time_in_force,
-  } = submit;
+/// A writer of stream messages to a file, one JSON object per line.
+struct Recorder {
+  path: PathBuf,
+  writer: LineWriter<File>,
+}
 
-  if stop_loss_limit_price.is_some() && stop_loss_stop_price.is_none() {
-    return Err(anyhow!(
-      "cannot create an one-triggers-other stop loss order without a
-       specified stop-loss-stop-price"
-    ))
+impl Recorder {
+  /// Create a recorder writing to the file at the given path,
+  /// truncating it if it exists.
+  fn create(path: &Path) -> Result<Self> {
+    let file =
+      File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let slf = Self {
+      path: path.to_path_buf(),
+      writer: LineWriter::new(file),
+    };
+    Ok(slf)
   }
-  let class = if take_profit_price.is_some() && stop_loss_stop_price.is_some() {
-    order::Class::Bracket
+
+  /// Record a message as received at the current time.
+  fn record(&mut self, message: RecordedMessage) -> Result<()> {
+    let record = Record {
+      time: Utc::now(),
+      message,
+    };
+    let json = to_json(&record).context("failed to serialize stream message")?;
+    writeln!(self.writer, "{json}")
+      .with_context(|| format!("failed to write to {}", self.path.display()))
+  }
+}
+
+
+/// Load the records contained in a file created via `--record`.
+///
+/// Records are read lazily, one line at a time, so that arbitrarily
+/// large recordings can be processed.
+fn load_recording(path: &Path) -> Result<impl Iterator<Item = Result<Record>> + '_> {
+  let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+  let records = BufReader::new(file)
+    .lines()
+    .zip(1..)
+    .filter_map(move |(result, number)| {
+      let line = match result
+        .with_context(|| format!("failed to read line {number} of {}", path.display()))
+      {
+        Ok(line) => line,
+        Err(err) => return Some(Err(err)),
+      };
+
+      if line.trim().is_empty() {
+        return None
+      }
+
+      let record = from_json::<Record>(&line)
+        .with_context(|| format!("failed to parse line {number} of {}", path.display()));
+      Some(record)
+    });
+  Ok(records)
+}
+
+
+/// Replay updates recorded via `--record`, rendering them the same way
+/// as they were when received.
+async fn replay_updates(
+  path: &Path,
+  speed: f64,
+  compact: bool,
+  currency: &str,
+  output: Output,
+) -> Result<()> {
+  ensure!(
+    speed.is_finite() && speed >= 0.0,
+    "replay speed must be a non-negative number"
+  );
+
+  let mut previous = None::<DateTime<Utc>>;
+
+  for record in load_recording(path)? {
+    let Record { time, message } = record?;
+    if let Some(previous) = previous {
+      if speed > 0.0 {
+        let gap = (time - previous).to_std().unwrap_or_default();
+        let delay = StdDuration::try_from_secs_f64(gap.as_secs_f64() / speed)
+          .with_context(|| format!("replay speed {speed} is too low"))?;
+        let () = sleep(delay).await;
+      }
+    }
+    previous = Some(time);
+
+    let () = match message.to_data() {
+      Some(Ok(data)) => print_realtime_data(&data, compact, output)?,
+      Some(Err(err)) => warn!("failed to parse market data update: {err}"),
+      None => match message {
+        RecordedMessage::TradeUpdate(update) => print_trade_update(&update, currency, output)?,
+        RecordedMessage::Unparsable { error } => warn!("recorded message failed to parse: {error}"),
+        // Market data updates are handled above.
+        _ => (),
+      },
+    };
+  }
+  Ok(())
+}
+
+
+/// Print a trade update.
+fn print_trade_update(update: &updates::OrderUpdate, currency: &str, output: Output) -> Result<()> {
+  if output == Output::Json {
+    return print_json_line(update)
+  }
+
+  println!(
+    r#"{symbol} {status}:
+  order id:       {id}
+  status:         {order_status}
+  type:           {type_}
+  side:           {side}
+  time-in-force:  {time_in_force}
+  {amount_type:15} {amount}
+  filled:         {filled}
+"#,
+    symbol = update.order.symbol,
+    status = format_trade_status(update.event),
+    id = update.order.id.as_hyphenated(),
+    order_status = format_order_status(update.order.status),
+    type_ = format_order_type(update.order.type_),
+    side = format_order_side(update.order.side),
+    time_in_force = format_time_in_force(update.order.time_in_force),
+    amount_type = format_amount_type(&update.order.amount).to_string() + ":",
+    amount = format_amount(&update.order.amount, currency),
+    filled = update.order.filled_quantity,
+  );
+  Ok(())
+}
+
+
+async fn stream_trade_updates(
+  client: Client,
+  reconnect: bool,
+  record: Option<PathBuf>,
+  output: Output,
+) -> Result<()> {
+  let currency = client
+    .issue::<account::Get>(&())
+    .await
+    .context("failed to retrieve account information")?
+    .currency;
+  let mut recorder = record.as_deref().map(Recorder::create).transpose()?;
+
+  let mut backoff = Backoff::default();
+  // The time we last knew to be up-to-date, if we are reconnecting.
+  let mut disconnected = None::<DateTime<Utc>>;
+  // The submission time of the oldest order that may get updated.
+  let mut horizon = None::<DateTime<Utc>>;
+
+  loop {
+    let result = client
+      .subscribe::<updates::OrderUpdates>()
+      .await
+      .with_context(|| "failed to subscribe to trade updates");
+    let (mut stream, _subscription) = match result {
+      Ok(result) => result,
+      Err(err) if disconnected.is_some() => {
+        let () = wait_for_reconnect("trade update", Some(err), &mut backoff).await;
+        continue
+      },
+      Err(err) => return Err(err),
+    };
+
+    if let Some(last_seen) = disconnected.take() {
+      let () = log_gap("trade update", last_seen);
+      let () = backoff.reset();
+
+      // Orders may have been filled while we were disconnected; report
+      // their current state so that no fills are lost. Some of these
+      // may duplicate updates we have already seen. Failure to do so
+      // should not bring down an otherwise working stream, though.
+      match missed_trade_updates(&client, last_seen, horizon).await {
+        Ok(updates) => {
+          for update in updates {
+            if let Some(recorder) = &mut recorder {
+              let () = recorder.record(RecordedMessage::TradeUpdate(update.clone()))?;
+            }
+            let () = print_trade_update(&update, &currency, output)?;
+          }
+        },
+        Err(err) => warn!("{err:#}; missed trade updates are not reported"),
+      }
+    }
+
+    if reconnect {
+      // If we fail to determine a new horizon the previous one, if
+      // any, stays valid: orders open now were either open back then
+      // or submitted afterwards.
+      match order_horizon(&client).await {
+        Ok(time) => horizon = Some(time),
+        Err(err) => warn!("{err:#}"),
+      }
+    }
+
+    let mut last_seen = Utc::now();
+    let error = loop {
+      match stream.next().await {
+        Some(Ok(Ok(update))) => {
+          last_seen = Utc::now();
+          if let Some(recorder) = &mut recorder {
+            let () = recorder.record(RecordedMessage::TradeUpdate(update.clone()))?;
+          }
+          let () = print_trade_update(&update, &currency, output)?;
+        },
+        Some(Ok(Err(err))) => {
+          if let Some(recorder) = &mut recorder {
+            let error = err.to_string();
+            let () = recorder.record(RecordedMessage::Unparsable { error })?;
+          }
+          warn!("failed to parse trade update: {err}")
+        },
+        Some(Err(err)) => break Some(Error::from(err)),
+        None => break None,
+      }
+    };
+
+    if !reconnect {
+      return error.map_or(Ok(()), Err)
+    }
+
+    let () = wait_for_reconnect("trade update", error, &mut backoff).await;
+    disconnected = Some(last_seen);
+  }
+}
+
+
+/// The JSON representation of a realtime market data update.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonData<'d> {
+  Bar(&'d stream::Bar),
+  Quote(&'d stream::Quote),
+  Trade(&'d stream::Trade),
+}
+
+
+/// Format a realtime market data update on a single line.
+fn format_data_compact(data: &stream::Data) -> Option<String> {
+  let line = match data {
+    stream::Data::Bar(bar) => format!(
+      "{timestamp} {symbol} bar o {open} h {high} l {low} c {close} v {volume}",
+      timestamp = format_local_time_short(bar.timestamp),
+      symbol = bar.symbol,
+      open = bar.open_price,
+      high = bar.high_price,
+      low = bar.low_price,
+      close = bar.close_price,
+      volume = bar.volume,
+    ),
+    stream::Data::Quote(quote) => format!(
+      "{timestamp} {symbol} quote bid {bid_price} x {bid_size} ask {ask_price} x {ask_size}",
+      timestamp = format_local_time_short(quote.timestamp),
+      symbol = quote.symbol,
+      bid_price = quote.bid_price,
+      bid_size = quote.bid_size,
+      ask_price = quote.ask_price,
+      ask_size = quote.ask_size,
+    ),
+    stream::Data::Trade(trade) => format!(
+      "{timestamp} {symbol} trade {size} @ {price}",
+      timestamp = format_local_time_short(trade.timestamp),
+      symbol = trade.symbol,
+      size = trade.trade_size,
+      price = trade.trade_price,
+    ),
+    _ => return None,
+  };
+  Some(line)
+}
+
+
+/// Format a realtime market data update in a verbose, multi-line form.
+fn format_data(data: &stream::Data) -> Option<String> {
+  let text = match data {
+    stream::Data::Bar(bar) => format!(
+      r#"{symbol}:
+  time stamp:    {timestamp}
+  open price:    {open_price}
+  close price:   {close_price}
+  high price:    {high_price}
+  low price:     {low_price}
+  volume:        {volume}"#,
+      symbol = bar.symbol,
+      timestamp = format_local_time_short(bar.timestamp),
+      open_price = bar.open_price,
+      close_price = bar.close_price,
+      high_price = bar.high_price,
+      low_price = bar.low_price,
+      volume = bar.volume,
+    ),
+    stream::Data::Quote(quote) => format!(
+      r#"{symbol}:
+  time stamp:    {timestamp}
+  bid price:     {bid_price}
+  bid size:      {bid_size}
+  ask price:     {ask_price}
+  ask size:      {ask_size}"#,
+      symbol = quote.symbol,
+      timestamp = format_local_time_short(quote.timestamp),
+      bid_price = quote.bid_price,
+      bid_size = quote.bid_size,
+      ask_price = quote.ask_price,
+      ask_size = quote.ask_size,
+    ),
+    stream::Data::Trade(trade) => format!(
+      r#"{symbol}:
+  time stamp:    {timestamp}
+  trade id:      {id}
+  price:         {price}
+  size:          {size}"#,
+      symbol = trade.symbol,
+      timestamp = format_local_time_short(trade.timestamp),
+      id = trade.trade_id,
+      price = trade.trade_price,
+      size = trade.trade_size,
+    ),
+    _ => return None,
+  };
+  Some(text)
+}
+
+
+/// Print a realtime market data update.
+fn print_realtime_data(data: &stream::Data, compact: bool, output: Output) -> Result<()> {
+  if output == Output::Json {
+    let json = match data {
+      stream::Data::Bar(bar) => JsonData::Bar(bar),
+      stream::Data::Quote(quote) => JsonData::Quote(quote),
+      stream::Data::Trade(trade) => JsonData::Trade(trade),
+      _ => {
+        warn!("received unexpected stream element: {:?}", data);
+        return Ok(())
+      },
+    };
+    return print_json_line(&json)
+  }
+
+  let text = if compact {
+    format_data_compact(data)
+  } else {
+    format_data(data)
+  };
+  match text {
+    Some(text) => println!("{text}"),
+    None => warn!("received unexpected stream element: {:?}", data),
+  }
+  Ok(())
+}
+
+
+/// Subscribe to realtime market data, invoking `handle` for each
+/// update until it breaks or the stream ends.
+///
+/// Received messages are recorded via `recorder`, if provided.
+async fn stream_market_data<F>(
+  client: &Client,
+  source: DataSource,
+  data: &stream::MarketData,
+  reconnect: bool,
+  mut recorder: Option<&mut Recorder>,
+  mut handle: F,
+) -> Result<()>
+where
+  F: FnMut(stream::Data) -> Result<ControlFlow<()>>,
+{
+  let mut backoff = Backoff::default();
+  // The time we last knew to be up-to-date, if we are reconnecting.
+  let mut disconnected = None::<DateTime<Utc>>;
+
+  loop {
+    let result = async {
+      let result = match source {
+        DataSource::Iex => {
+          client
+            .subscribe::<stream::RealtimeData<stream::IEX, JsonValue, JsonValue, JsonValue>>()
+            .await
+        },
+        DataSource::Sip => {
+          client
+            .subscribe::<stream::RealtimeData<stream::SIP, JsonValue, JsonValue, JsonValue>>()
+            .await
+        },
+      };
+
+      let (mut stream, mut subscription) =
+        result.with_context(|| "failed to subscribe to realtime market data updates")?;
+
+      // Every connection starts out without any subscriptions, so we
+      // (re-)establish the full set each time.
+      let subscribe = subscription.subscribe(data).boxed_local().fuse();
+      let () = stream::drive(subscribe, &mut stream)
+        .await
+        .map_err(|result| {
+          result
+            .map(|result| apca::Error::Json(result.unwrap_err()))
+            .map_err(apca::Error::WebSocket)
+            .unwrap_or_else(|err| err)
+        })
+        .context("failed to subscribe to market data")???;
+      Ok::<_, Error>((stream, subscription))
+    }
+    .await;
+
+    let (mut stream, _subscription) = match result {
+      Ok(result) => result,
+      Err(err) if disconnected.is_some() => {
+        let () = wait_for_reconnect("market data", Some(err), &mut backoff).await;
+        continue
+      },
+      Err(err) => return Err(err),
+    };
+
+    if let Some(last_seen) = disconnected.take() {
+      let () = log_gap("market data", last_seen);
+      let () = backoff.reset();
+    }
+
+    let mut last_seen = Utc::now();
+    let error = loop {
+      match stream.next().await {
+        Some(Ok(Ok(data))) => {
+          last_seen = Utc::now();
+          let message = match RecordedMessage::from_data(data) {
+            Ok(message) => message,
+            Err(data) => {
+              warn!("received unexpected stream element: {:?}", data);
+              continue
+            },
+          };
+          // We parse before recording, because the message is moved
+          // into the recording.
+          let result = message.to_data();
+          if let Some(recorder) = &mut recorder {
+            let () = recorder.record(message)?;
+          }
+
+          match result {
+            Some(Ok(data)) => {
+              if handle(data)?.is_break() {
+                return Ok(())
+              }
+            },
+            Some(Err(err)) => warn!("failed to parse market data update: {err}"),
+            // SANITY: Messages created from market data updates are
+            //         always market data updates.
+            None => unreachable!(),
+          }
+        },
+        Some(Ok(Err(err))) => {
+          if let Some(recorder) = &mut recorder {
+            let error = err.to_string();
+            let () = recorder.record(RecordedMessage::Unparsable { error })?;
+          }
+          warn!("failed to parse market data update: {err}")
+        },
+        Some(Err(err)) => break Some(Error::from(err)),
+        None => break None,
+      }
+    };
+
+    if !reconnect {
+      return error.map_or(Ok(()), Err)
+    }
+
+    let () = wait_for_reconnect("market data", error, &mut backoff).await;
+    disconnected = Some(last_seen);
+  }
+}
+
+
+/// Subscribe to and stream realtime market data updates.
+async fn stream_realtime_data(
+  client: Client,
+  source: DataSource,
+  data: stream::MarketData,
+  compact: bool,
+  reconnect: bool,
+  record: Option<PathBuf>,
+  output: Output,
+) -> Result<()> {
+  let mut recorder = record.as_deref().map(Recorder::create).transpose()?;
+
+  stream_market_data(
+    &client,
+    source,
+    &data,
+    reconnect,
+    recorder.as_mut(),
+    |data| {
+      let () = print_realtime_data(&data, compact, output)?;
+      Ok(ControlFlow::Continue(()))
+    },
+  )
+  .await
+}
+
+
+/// Add a price alert.
+fn alert_add(add: AddAlert, output: Output) -> Result<()> {
+  let AddAlert {
+    symbol,
+    above,
+    below,
+    change,
+    exec,
+    rearm,
+  } = add;
+
+  let condition = match (above, below, change) {
+    (Some(above), None, None) => alert::Condition::Above(above),
+    (None, Some(below), None) => alert::Condition::Below(below),
+    (None, None, Some(change)) => {
+      ensure!(!change.is_zero(), "percent change must not be zero");
+      alert::Condition::Change(change)
+    },
+    // Other combinations should never happen as ensured by `clap`.
+    _ => unreachable!(),
+  };
+
+  let path = config::alerts_path()?;
+  let mut alerts = alert::Alerts::load(&path)?;
+  let alert = alerts
+    .add(symbol.to_uppercase(), condition, exec, rearm)
+    .clone();
+  let () = alerts.save(&path)?;
+
+  if output == Output::Json {
+    return print_json(&alert)
+  }
+
+  println!("{}", alert.id);
+  Ok(())
+}
+
+
+/// List all price alerts.
+fn alert_list(output: Output) -> Result<()> {
+  let path = config::alerts_path()?;
+  let alerts = alert::Alerts::load(&path)?.alerts;
+
+  if output == Output::Json {
+    return print_json(&alerts)
+  }
+
+  let id_max = max_width(&alerts, |a| a.id.to_string().len());
+  let sym_max = max_width(&alerts, |a| a.symbol.len());
+  let cond_max = max_width(&alerts, |a| a.condition.to_string().len());
+
+  for alert in alerts {
+    let line = format!(
+      "{id:>id_max$} {sym:<sym_max$} {condition:<cond_max$} {mode:<5} {command}",
+      id = alert.id,
+      sym = alert.symbol,
+      condition = alert.condition.to_string(),
+      mode = if alert.rearm { "rearm" } else { "once" },
+      command = alert.command.as_deref().unwrap_or(""),
+    );
+    println!("{}", line.trim_end());
+  }
+  Ok(())
+}
+
+
+/// Remove price alerts.
+fn alert_remove(ids: Vec<u64>) -> Result<()> {
+  let path = config::alerts_path()?;
+  let mut alerts = alert::Alerts::load(&path)?;
+  for id in ids {
+    ensure!(alerts.remove(id), "alert {id} not found");
+  }
+  alerts.save(&path)
+}
+
+
+/// The JSON representation of a fired alert.
+#[derive(Debug, Serialize)]
+struct JsonFiredAlert<'a> {
+  id: u64,
+  symbol: &'a str,
+  condition: &'a alert::Condition,
+  price: &'a Num,
+  time: DateTime<Utc>,
+}
+
+
+/// Fire an alert, by running its command or printing a line.
+fn fire_alert(alert: &alert::Alert, price: &Num, output: Output) -> Result<()> {
+  match &alert.command {
+    Some(command) => {
+      let result = Process::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("APCA_ALERT_ID", alert.id.to_string())
+        .env("APCA_ALERT_SYMBOL", &alert.symbol)
+        .env("APCA_ALERT_CONDITION", alert.condition.to_string())
+        .env("APCA_ALERT_PRICE", price.to_string())
+        .spawn();
+
+      match result {
+        Ok(mut child) => {
+          let id = alert.id;
+          // We don't want to hold up processing of market data while
+          // the command runs, but still reap it eventually.
+          let _handle = std::thread::spawn(move || match child.wait() {
+            Ok(status) if !status.success() => {
+              warn!("command for alert {id} failed: {status}")
+            },
+            Ok(_) => (),
+            Err(err) => warn!("failed to wait for command of alert {id}: {err}"),
+          });
+        },
+        Err(err) => warn!("failed to run command for alert {}: {err}", alert.id),
+      }
+      Ok(())
+    },
+    None if output == Output::Json => {
+      let fired = JsonFiredAlert {
+        id: alert.id,
+        symbol: &alert.symbol,
+        condition: &alert.condition,
+        price,
+        time: Utc::now(),
+      };
+      print_json_line(&fired)
+    },
+    None => {
+      println!(
+        "{time} alert {id}: {symbol} {condition} @ {price}",
+        time = format_local_time_short(Utc::now()),
+        id = alert.id,
+        symbol = alert.symbol,
+        condition = alert.condition,
+      );
+      Ok(())
+    },
+  }
+}
+
+
+/// Retrieve the close prices that percent changes of the given symbols
+/// on the given trading day are relative to.
+async fn previous_closes(
+  client: &Client,
+  source: DataSource,
+  symbols: &BTreeSet<String>,
+  day: NaiveDate,
+) -> Result<HashMap<String, Num>> {
+  if symbols.is_empty() {
+    return Ok(HashMap::new())
+  }
+
+  let request = endpoint::SnapshotsReq {
+    symbols: symbols.iter().cloned().collect(),
+    feed: source.to_feed(),
+  };
+  let closes = client
+    .issue::<endpoint::Snapshots>(&request)
+    .await
+    .context("failed to retrieve previous close prices")?
+    .into_iter()
+    .filter_map(|(symbol, snapshot)| {
+      // Before the market opens the daily bar still is the one of the
+      // previous trading day.
+      let close = match snapshot.daily_bar {
+        Some(bar) if trading_day(&bar.time) < day => bar.close,
+        _ => snapshot.prev_daily_bar?.close,
+      };
+      Some((symbol, close))
+    })
+    .collect::<HashMap<_, _>>();
+
+  for symbol in symbols
+    .iter()
+    .filter(|symbol| !closes.contains_key(*symbol))
+  {
+    warn!("no previous close known for {symbol}; percent change alerts will not fire");
+  }
+  Ok(closes)
+}
+
+
+/// Determine the trading day (in the America/New_York time zone) that a
+/// point in time falls on.
+fn trading_day(time: &DateTime<Utc>) -> NaiveDate {
+  New_York.from_utc_datetime(&time.naive_utc()).date_naive()
+}
+
+
+/// Evaluate the alerts watching a symbol for a new price, firing those
+/// whose conditions are met.
+fn check_alerts(
+  watches: &mut Vec<alert::Watch>,
+  symbol: &str,
+  price: &Num,
+  closes: &HashMap<String, Num>,
+  path: &Path,
+  output: Output,
+) -> Result<ControlFlow<()>> {
+  for watch in watches
+    .iter_mut()
+    .filter(|watch| watch.alert.symbol == symbol)
+  {
+    if watch.update(price, closes.get(symbol)) {
+      let () = fire_alert(&watch.alert, price, output)?;
+
+      if watch.is_done() {
+        // One-shot alerts are removed once fired, so that they don't
+        // fire again when watching next time.
+        let mut alerts = alert::Alerts::load(path)?;
+        let _removed = alerts.remove(watch.alert.id);
+        let () = alerts.save(path)?;
+      }
+    }
+  }
+
+  let () = watches.retain(|watch| !watch.is_done());
+  if watches.is_empty() {
+    Ok(ControlFlow::Break(()))
+  } else {
+    Ok(ControlFlow::Continue(()))
+  }
+}
+
+
+/// Watch market data and fire alerts whose conditions are met.
+async fn alert_watch(
+  client: Client,
+  source: DataSource,
+  bars: bool,
+  reconnect: bool,
+  output: Output,
+) -> Result<()> {
+  let path = config::alerts_path()?;
+  let alerts = alert::Alerts::load(&path)?.alerts;
+  ensure!(!alerts.is_empty(), "no alerts defined");
+
+  let symbols = alerts
+    .iter()
+    .map(|alert| alert.symbol.clone())
+    .collect::<BTreeSet<_>>();
+  let close_symbols = alerts
+    .iter()
+    .filter(|alert| alert.condition.needs_close())
+    .map(|alert| alert.symbol.clone())
+    .collect::<BTreeSet<_>>();
+
+  let mut watches = alerts
+    .into_iter()
+    .map(alert::Watch::new)
+    .collect::<Vec<_>>();
+
+  let symbols = symbols.into_iter().collect::<Vec<_>>();
+  let mut data = stream::MarketData::default();
+  if bars {
+    data.set_bars(symbols);
+  } else {
+    data.set_trades(symbols);
+  }
+
+  let mut day = trading_day(&Utc::now());
+  // A price from a new trading day, still to be evaluated.
+  let mut pending = None::<(String, Num)>;
+
+  loop {
+    // Percent changes are evaluated relative to the close of the
+    // trading day before the one a price is from. Hence, we retrieve
+    // the closes anew whenever a new trading day starts.
+    let closes = previous_closes(&client, source, &close_symbols, day).await?;
+
+    if let Some((symbol, price)) = pending.take() {
+      if check_alerts(&mut watches, &symbol, &price, &closes, &path, output)?.is_break() {
+        return Ok(())
+      }
+    }
+
+    let mut next = None;
+    let () = stream_market_data(&client, source, &data, reconnect, None, |data| {
+      let (symbol, price, time) = match &data {
+        stream::Data::Bar(bar) => (&bar.symbol, &bar.close_price, &bar.timestamp),
+        stream::Data::Trade(trade) => (&trade.symbol, &trade.trade_price, &trade.timestamp),
+        _ => return Ok(ControlFlow::Continue(())),
+      };
+
+      let date = trading_day(time);
+      if date > day {
+        next = Some((date, symbol.clone(), price.clone()));
+        return Ok(ControlFlow::Break(()))
+      }
+      check_alerts(&mut watches, symbol, price, &closes, &path, output)
+    })
+    .await?;
+
+    match next {
+      Some((date, symbol, price)) => {
+        day = date;
+        pending = Some((symbol, price));
+      },
+      None => return Ok(()),
+    }
+  }
+}
+
+
+/// The handler for the 'alert' command, excluding watching.
+fn alert(alert: Alert, output: Output) -> Result<()> {
+  match alert {
+    Alert::Add(add) => alert_add(add, output),
+    Alert::List => alert_list(output),
+    Alert::Remove { ids } => alert_remove(ids),
+    // Watching requires a client and is handled separately.
+    Alert::Watch { .. } => unreachable!(),
+  }
+}
+
+async fn updates(client: Client, updates: Updates, output: Output) -> Result<()> {
+  match updates {
+    Updates::Trades { reconnect, record } => {
+      stream_trade_updates(client, reconnect, record, output).await
+    },
+    Updates::Data {
+      symbols,
+      source,
+      quotes,
+      trades,
+      bars,
+      compact,
+      reconnect,
+      record,
+    } => {
+      let mut data = stream::MarketData::default();
+      // Bars are what we stream unless anything else was asked for.
+      if bars || !(quotes || trades) {
+        data.set_bars(symbols.clone());
+      }
+      if quotes {
+        data.set_quotes(symbols.clone());
+      }
+      if trades {
+        data.set_trades(symbols);
+      }
+      stream_realtime_data(client, source, data, compact, reconnect, record, output).await
+    },
+    // Replaying is handled before a client is created.
+    Updates::Replay { .. } => unreachable!(),
+  }
+}
+
+/// Print the current market status.
+async fn market(client: Client, output: Output) -> Result<()> {
+  let clock = client
+    .issue::<clock::Get>(&())
+    .await
+    .with_context(|| "failed to retrieve market clock")?;
+
+  if output == Output::Json {
+    return print_json(&clock)
+  }
+
+  println!(
+    r#"market:
+  open:         {open}
+  current time: {current}
+  next open:    {next_open}
+  next close:   {next_close}"#,
+    open = clock.open,
+    current = format_local_time(clock.current),
+    next_open = format_local_time(clock.next_open),
+    next_close = format_local_time(clock.next_close),
+  );
+  Ok(())
+}
+
+
+/// Retrieve the price at which an order for the given symbol would
+/// likely be filled, based on the most recent quote.
+async fn estimate_price(client: &Client, symbol: &str, side: order::Side) -> Result<Num> {
+  let request = last_quotes::GetReqInit::default().init([symbol]);
+  let mut quotes = client
+    .issue::<last_quotes::Get>(&request)
+    .await
+    .with_context(|| format!("failed to retrieve last quote for {symbol}"))?;
+
+  let quote = match quotes.as_mut_slice() {
+    [(_symbol, quote)] => {
+      debug_assert_eq!(_symbol, symbol);
+      quote
+    },
+    _ => bail!(
+      "received unexpected number of quotes from Alpaca ({})",
+      quotes.len()
+    ),
+  };
+
+  let price = match side {
+    order::Side::Buy => &mut quote.ask_price,
+    order::Side::Sell => &mut quote.bid_price,
+  };
+
+  ensure!(
+    !price.is_zero(),
+    "most recent quote for {} contains price of zero; unable to estimate quantity",
+    symbol
+  );
+  Ok(take(price))
+}
+
+
+/// Convert a certain monetary value into the maximum number of shares
+/// purchasable (i.e., a quantity).
+async fn value_to_quantity(
+  client: &Client,
+  symbol: &str,
+  side: order::Side,
+  value: &Num,
+  price: Option<Num>,
+) -> Result<Num> {
+  let price = match price {
+    Some(price) => price,
+    None => estimate_price(client, symbol, side).await?,
+  };
+
+  Ok(value / price)
+}
+
+
+/// Check that a percentage of the account to trade is within bounds.
+fn validate_percent(percent: &Num) -> Result<()> {
+  ensure!(
+    percent.is_positive() && *percent <= Num::from(100),
+    "percentage must be greater than 0 and at most 100"
+  );
+  Ok(())
+}
+
+
+/// Convert a percentage of account equity or buying power, as
+/// selected by `select`, into a monetary value.
+async fn percent_to_value<F>(client: &Client, percent: &Num, select: F) -> Result<Num>
+where
+  F: FnOnce(account::Account) -> Num,
+{
+  let () = validate_percent(percent)?;
+  let account = client
+    .issue::<account::Get>(&())
+    .await
+    .with_context(|| "failed to retrieve account information")?;
+  Ok(percent_of(select(account), percent))
+}
+
+
+/// Calculate the given percentage of a value.
+fn percent_of(value: Num, percent: &Num) -> Num {
+  value * percent / 100
+}
+
+
+/// The position sizing derived from an amount to risk.
+#[derive(Clone, Debug, PartialEq)]
+struct Sizing {
+  /// The value to risk.
+  risk: Num,
+  /// The percentage of account equity the risk corresponds to, if it
+  /// was specified that way.
+  percent: Option<Num>,
+  /// The (estimated) entry price.
+  entry: Num,
+  /// The stop price.
+  stop: Num,
+  /// The value at risk per share.
+  distance: Num,
+  /// The derived quantity.
+  quantity: Num,
+}
+
+impl Sizing {
+  /// Compute the sizing for the given risk, entry, and stop.
+  fn new(
+    side: order::Side,
+    risk: Num,
+    percent: Option<Num>,
+    entry: Num,
+    stop: Num,
+  ) -> Result<Self> {
+    ensure!(risk.is_positive(), "risk must be positive");
+
+    let distance = match side {
+      order::Side::Buy => &entry - &stop,
+      order::Side::Sell => &stop - &entry,
+    };
+    ensure!(
+      distance.is_positive(),
+      "stop price {stop} has to be {} entry price {entry:.2}",
+      if side == order::Side::Buy {
+        "below"
+      } else {
+        "above"
+      },
+    );
+
+    let quantity = (&risk / &distance).trunc();
+    ensure!(
+      !quantity.is_zero(),
+      "risk of {risk:.2} does not cover a single share risking {distance:.2}"
+    );
+
+    let sizing = Self {
+      risk,
+      percent,
+      entry,
+      stop,
+      distance,
+      quantity,
+    };
+    Ok(sizing)
+  }
+
+  /// Format a summary of the sizing suitable for confirmation by the
+  /// user.
+  fn summary(&self) -> String {
+    let risk = match &self.percent {
+      Some(percent) => format!("~{:.2} ({percent}% of equity)", self.risk),
+      None => format!("{:.2}", self.risk),
+    };
+    format!(
+      r#"  risk:             {risk}
+  entry:            ~{entry:.2}
+  stop:             {stop:.2}
+  risk per share:   ~{distance:.2}"#,
+      entry = self.entry,
+      stop = self.stop,
+      distance = self.distance,
+    )
+  }
+}
+
+
+/// Derive the quantity to trade from an amount to risk, with the entry
+/// price being the limit price or, if absent, the last quote.
+async fn risk_to_sizing(
+  client: &Client,
+  symbol: &str,
+  side: order::Side,
+  risk: &Risk,
+  limit_price: Option<Num>,
+  stop: &Num,
+) -> Result<Sizing> {
+  let (risk, percent) = match risk {
+    Risk::Value(value) => (value.clone(), None),
+    Risk::Percent(percent) => {
+      let risk = percent_to_value(client, percent, |account| account.equity).await?;
+      (risk, Some(percent.clone()))
+    },
+  };
+
+  let entry = match limit_price {
+    Some(price) => price,
+    None => estimate_price(client, symbol, side).await?,
+  };
+  Sizing::new(side, risk, percent, entry, stop.clone())
+}
+
+
+/// Whether operations on the account need to be confirmed by the user
+/// before being carried out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Confirm {
+  /// Operations are performed right away.
+  Never,
+  /// Operations are performed only after the user confirmed them
+  /// interactively.
+  Interactive {
+    /// Whether the account is a live one (as opposed to a paper
+    /// trading one).
+    live: bool,
+  },
+}
+
+impl Confirm {
+  /// Ask the user to confirm the given action, described in more
+  /// detail by `summary`.
+  ///
+  /// An error is returned if the user declined or we are unable to
+  /// ask because stdin does not refer to a terminal.
+  fn ask(self, action: &str, summary: &str) -> Result<()> {
+    let live = match self {
+      Self::Never => return Ok(()),
+      Self::Interactive { live } => live,
+    };
+    let account = if live { "live" } else { "paper" };
+
+    let mut stdin = stdin().lock();
+    ensure!(
+      stdin.is_terminal(),
+      "refusing to {action} on {account} account without confirmation: \
+       stdin is not a terminal (use --yes to skip confirmation)"
+    );
+
+    // The prompt goes to stderr so as to not interfere with regular
+    // (and potentially machine readable) output.
+    let account = if live { "LIVE" } else { account };
+    eprintln!("About to {action} on {account} account:\n{summary}");
+    eprint!("Proceed? [y/N] ");
+
+    let mut answer = String::new();
+    let _count = io::BufRead::read_line(&mut stdin, &mut answer)
+      .context("failed to read confirmation from stdin")?;
+    match answer.trim().to_ascii_lowercase().as_str() {
+      "y" | "yes" => Ok(()),
+      _ => bail!("aborted: {action} not confirmed"),
+    }
+  }
+}
+
+
+/// An operation that would have been performed on the account, if not
+/// for `--dry-run`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum DryRun<'r> {
+  /// An order would have been submitted.
+  SubmitOrder { request: &'r order::CreateReq },
+  /// A batch of orders would have been submitted.
+  SubmitOrders { requests: Vec<&'r order::CreateReq> },
+  /// An order would have been changed.
+  ChangeOrder {
+    id: order::Id,
+    request: &'r order::ChangeReq,
+  },
+  /// The given orders would have been canceled.
+  CancelOrders {
+    #[serde(rename = "ids", serialize_with = "serialize_order_ids")]
+    orders: Vec<&'r order::Order>,
+  },
+  /// The positions in the given symbols would have been closed, after
+  /// canceling the given orders.
+  ClosePositions {
+    symbols: Vec<&'r str>,
+    #[serde(rename = "cancel_ids", serialize_with = "serialize_order_ids")]
+    orders: Vec<&'r order::Order>,
+  },
+  /// The position in the given symbol would have been closed (in
+  /// part, if a quantity or percentage is present).
+  ClosePosition {
+    symbol: &'r str,
+    #[serde(rename = "qty", skip_serializing_if = "Option::is_none")]
+    quantity: Option<&'r Num>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentage: Option<&'r Num>,
+  },
+}
+
+impl DryRun<'_> {
+  /// Print the operation.
+  ///
+  /// Requests are always shown in the JSON representation that would
+  /// have been sent to Alpaca.
+  fn print(&self, output: Output) -> Result<()> {
+    if output == Output::Json {
+      return print_json(self)
+    }
+
+    match self {
+      Self::SubmitOrder { request } => {
+        println!("would submit order:");
+        let () = print_json(request)?;
+      },
+      Self::SubmitOrders { requests } => {
+        println!("would submit {} order(s):", requests.len());
+        let () = print_json(requests)?;
+      },
+      Self::ChangeOrder { id, request } => {
+        println!("would change order {}:", id.as_hyphenated());
+        let () = print_json(request)?;
+      },
+      Self::CancelOrders { orders } => print_cancel_preview(orders),
+      Self::ClosePositions { symbols, orders } => {
+        if !orders.is_empty() {
+          let () = print_cancel_preview(orders);
+        }
+        println!("would close {} position(s)", symbols.len());
+        for symbol in symbols {
+          println!("  {symbol}");
+        }
+      },
+      Self::ClosePosition {
+        symbol,
+        quantity,
+        percentage,
+      } => match (quantity, percentage) {
+        (Some(quantity), _) => println!("would close {quantity} of position in {symbol}"),
+        (None, Some(percentage)) => {
+          println!("would close {percentage}% of position in {symbol}")
+        },
+        (None, None) => println!("would close position in {symbol}"),
+      },
+    }
+    Ok(())
+  }
+}
+
+
+/// Print a preview of the orders that would be canceled.
+fn print_cancel_preview(orders: &[&order::Order]) {
+  println!("would cancel {} order(s)", orders.len());
+  for order in orders {
+    println!(
+      "  {} {} {} {}",
+      order.id.as_hyphenated(),
+      order.symbol,
+      format_order_side(order.side),
+      format_order_type(order.type_),
+    );
+  }
+}
+
+
+/// Serialize a list of orders as the list of their IDs.
+fn serialize_order_ids<S>(orders: &[&order::Order], serializer: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  serializer.collect_seq(orders.iter().map(|order| order.id))
+}
+
+
+/// Format an order summary suitable for confirmation by the user.
+fn format_order_summary(
+  symbol: &str,
+  side: order::Side,
+  quantity: Option<&Num>,
+  notional: Option<&Num>,
+  type_: order::Type,
+  time_in_force: order::TimeInForce,
+) -> String {
+  let quantity = quantity
+    .map(|quantity| format!("~{quantity}"))
+    .unwrap_or_else(|| "N/A".to_string());
+  let notional = notional
+    .map(|notional| format!("~{notional:.2}"))
+    .unwrap_or_else(|| "N/A".to_string());
+
+  format!(
+    r#"  symbol:           {symbol}
+  side:             {side}
+  quantity:         {quantity}
+  notional:         {notional}
+  type:             {type_}
+  good until:       {tif}"#,
+    side = format_order_side(side),
+    type_ = format_order_type(type_),
+    tif = format_time_in_force(time_in_force),
+  )
+}
+
+
+/// Estimate the notional value of an order for the given quantity.
+///
+/// The estimate is purely informational and so failure to retrieve a
+/// quote is not considered fatal.
+async fn estimate_notional(
+  client: &Client,
+  symbol: &str,
+  side: order::Side,
+  quantity: &Num,
+  price: Option<&Num>,
+) -> Option<Num> {
+  let price = match price {
+    Some(price) => price.clone(),
+    None => estimate_price(client, symbol, side).await.ok()?,
+  };
+  Some(quantity * price)
+}
+
+
+/// The handler for the 'order' command.
+async fn order(
+  client: Client,
+  order: Order,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
+  match order {
+    Order::Submit(submit) => order_submit(client, submit, confirm, dry_run, output).await,
+    Order::SubmitBatch(batch) => order_submit_batch(client, batch, confirm, dry_run, output).await,
+    Order::Change(change) => order_change(client, change, confirm, dry_run, output).await,
+    Order::Cancel(cancel) => order_cancel(client, cancel, confirm, dry_run, output).await,
+    Order::Get { id } => order_get(client, id, output).await,
+    Order::List(list) => order_list(client, list, output).await,
+    Order::Wait { id, timeout } => order_wait(client, id, timeout, output).await,
+  }
+}
+
+
+/// Determine the time-in-force of an order, taking into account that
+/// Alpaca only accepts fractional and notional orders valid for the
+/// day.
+fn determine_time_in_force(
+  time_in_force: Option<TimeInForce>,
+  fractional: bool,
+) -> Result<order::TimeInForce> {
+  let time_in_force = match time_in_force {
+    Some(time_in_force) => {
+      ensure!(
+        !fractional || matches!(time_in_force, TimeInForce::Today),
+        "fractional and notional orders can only be valid for the day ('today')"
+      );
+      time_in_force
+    },
+    None if fractional => TimeInForce::Today,
+    None => TimeInForce::Canceled,
+  };
+  Ok(time_in_force.to_time_in_force())
+}
+
+
+/// Determine the type of an order by looking at the limit and stop
+/// prices, if any, as well as whether the stop is trailing.
+fn determine_order_type(
+  limit_price: &Option<Num>,
+  stop_price: &Option<Num>,
+  trailing: bool,
+) -> order::Type {
+  if trailing {
+    debug_assert!(limit_price.is_none() && stop_price.is_none());
+    return order::Type::TrailingStop
+  }
+
+  match (limit_price.is_some(), stop_price.is_some()) {
+    (true, true) => order::Type::StopLimit,
+    (true, false) => order::Type::Limit,
+    (false, true) => order::Type::Stop,
+    (false, false) => order::Type::Market,
+  }
+}
+
+
+/// Check that the take-profit and stop-loss prices of a one-cancels-other
+/// order are on the correct side of the current market price.
+fn validate_oco_prices(
+  side: order::Side,
+  price: &Num,
+  take_profit: &Num,
+  stop_loss: &Num,
+) -> Result<()> {
+  match side {
+    // A sell order exits a long position: we take profit above and
+    // stop our losses below the current price.
+    order::Side::Sell => {
+      ensure!(
+        take_profit > price,
+        "take-profit price {take_profit} must be above current bid price {price} for sell order"
+      );
+      ensure!(
+        stop_loss < price,
+        "stop-loss stop price {stop_loss} must be below current bid price {price} for sell order"
+      );
+    },
+    // A buy order exits a short position, so it's the other way round.
+    order::Side::Buy => {
+      ensure!(
+        take_profit < price,
+        "take-profit price {take_profit} must be below current ask price {price} for buy order"
+      );
+      ensure!(
+        stop_loss > price,
+        "stop-loss stop price {stop_loss} must be above current ask price {price} for buy order"
+      );
+    },
+  }
+  Ok(())
+}
+
+
+/// An order submission that passed validation.
+#[derive(Debug)]
+struct Submission {
+  /// The symbol of the asset to trade.
+  symbol: String,
+  /// The value to trade, if the order was specified that way.
+  value: Option<Num>,
+  /// The sizing derived from the amount to risk, if the order was
+  /// specified that way.
+  sizing: Option<Sizing>,
+  /// The request to send to Alpaca.
+  request: order::CreateReq,
+}
+
+impl Submission {
+  /// Format a summary of the submission suitable for confirmation by
+  /// the user.
+  async fn summary(&self, client: &Client) -> String {
+    let request = &self.request;
+    let quantity = match &request.amount {
+      order::Amount::Quantity { quantity } => Some(quantity),
+      order::Amount::Notional { .. } => None,
+    };
+    let notional = match (&self.value, quantity) {
+      (Some(value), _) => Some(value.clone()),
+      (None, Some(quantity)) => {
+        let price = request.limit_price.as_ref().or(request.stop_price.as_ref());
+        estimate_notional(client, &self.symbol, request.side, quantity, price).await
+      },
+      (None, None) => None,
+    };
+
+    let summary = format_order_summary(
+      &self.symbol,
+      request.side,
+      quantity,
+      notional.as_ref(),
+      request.type_,
+      request.time_in_force,
+    );
+
+    match &self.sizing {
+      Some(sizing) => format!("{summary}\n{}", sizing.summary()),
+      None => summary,
+    }
+  }
+}
+
+
+/// Validate an order submission and create the request to send to
+/// Alpaca.
+///
+/// Depending on the order, the most recent quote for the symbol may be
+/// retrieved as part of this step.
+async fn prepare_submission(client: &Client, submit: SubmitOrder) -> Result<Submission> {
+  let SubmitOrder {
+    side,
+    symbol,
+    quantity,
+    value,
+    percent_equity,
+    percent_buying_power,
+    risk,
+    fractional,
+    notional,
+    limit_price,
+    stop_price,
+    trail_price,
+    trail_percent,
+    take_profit_price,
+    stop_loss_stop_price,
+    stop_loss_limit_price,
+    oco,
+    extended_hours,
+    time_in_force,
+    client_order_id,
+    wait: _,
+    timeout: _,
+  } = submit;
+
+  let client_order_id = match client_order_id {
+    Some(id) => {
+      ensure!(
+        !id.is_empty() && id.chars().count() <= MAX_CLIENT_ORDER_ID_LEN,
+        "client order ID must be between 1 and {MAX_CLIENT_ORDER_ID_LEN} characters long"
+      );
+      id
+    },
+    None => Uuid::new_v4().as_hyphenated().to_string(),
+  };
+
+  if stop_loss_limit_price.is_some() && stop_loss_stop_price.is_none() {
+    return Err(anyhow!(
+      "cannot create an one-triggers-other stop loss order without a
+       specified stop-loss-stop-price"
+    ))
+  }
+  let class = if oco {
+    order::Class::OneCancelsOther
+  } else if take_profit_price.is_some() && stop_loss_stop_price.is_some() {
+    order::Class::Bracket
   } else if take_profit_price.is_some() || stop_loss_stop_price.is_some() {
     order::Class::OneTriggersOther
   } else {
@@ -969,26 +2686,92 @@ async fn order_submit(client: Client, submit: SubmitOrder) -> Result<()> {
     Side::Sell => order::Side::Sell,
   };
 
-  let quantity = match (quantity, value) {
-    (Some(quantity), None) => quantity,
-    (None, Some(value)) => {
-      let quantity = value_to_quantity(&client, &symbol, side, &value, limit_price.clone())
+  let value = match (value, percent_equity, percent_buying_power) {
+    (Some(value), None, None) => Some(value),
+    (None, Some(percent), None) => {
+      let value = percent_to_value(client, &percent, |account| account.equity)
+        .await
+        .with_context(|| "unable to convert percentage of equity to value")?;
+      Some(value)
+    },
+    (None, None, Some(percent)) => {
+      let value = percent_to_value(client, &percent, |account| account.buying_power)
+        .await
+        .with_context(|| "unable to convert percentage of buying power to value")?;
+      Some(value)
+    },
+    (None, None, None) => None,
+    // Other combinations should never happen as ensured by `clap`.
+    _ => unreachable!(),
+  };
+
+  let (amount, value, sizing) = match (quantity, value, risk) {
+    (Some(quantity), None, None) => (order::Amount::quantity(quantity), None, None),
+    (None, Some(value), None) if notional => {
+      (order::Amount::notional(value.clone()), Some(value), None)
+    },
+    (None, Some(value), None) => {
+      let quantity = value_to_quantity(client, &symbol, side, &value, limit_price.clone())
         .await
-        .with_context(|| "unable to convert value to quantity")?
+        .with_context(|| "unable to convert value to quantity")?;
+      let quantity = if fractional {
+        let asset = client
+          .issue::<asset::Get>(&asset::Symbol::Sym(symbol.clone()))
+          .await
+          .with_context(|| format!("failed to retrieve asset information for {symbol}"))?;
+        ensure!(
+          asset.fractionable,
+          "{symbol} is not fractionable; unable to trade fractional quantity"
+        );
+        round_quantity(&quantity, Rounding::Fractional, true)
+      } else {
         // We `round` as opposed to `trunc` to have a little less bias
         // in there and in an attempt to treat short orders equally.
-        // Strictly speaking, with fractional trading enabled, we could
-        // just leave the fractional value as-is, but it's not
-        // guaranteed that the account has that enabled or whether it's
-        // really desired by the user.
-        .round();
-      quantity
+        // Fractional quantities have to be asked for explicitly, as
+        // it's not guaranteed that the account has fractional trading
+        // enabled.
+        quantity.round()
+      };
+      (order::Amount::quantity(quantity), Some(value), None)
+    },
+    (None, None, Some(risk)) => {
+      let stop = stop_loss_stop_price
+        .as_ref()
+        .or(stop_price.as_ref())
+        .context("sizing by risk requires a stop-loss stop price or a stop price")?;
+      let sizing = risk_to_sizing(client, &symbol, side, &risk, limit_price.clone(), stop)
+        .await
+        .with_context(|| "unable to derive quantity from risk")?;
+      (
+        order::Amount::quantity(sizing.quantity.clone()),
+        None,
+        Some(sizing),
+      )
     },
     // Other combinations should never happen as ensured by `clap`.
     _ => unreachable!(),
   };
 
-  let type_ = determine_order_type(&limit_price, &stop_price);
+  if oco {
+    // SANITY: `clap` ensures that both prices are present for OCO
+    //         orders.
+    let take_profit = take_profit_price.as_ref().unwrap();
+    let stop_loss = stop_loss_stop_price.as_ref().unwrap();
+    let price = estimate_price(client, &symbol, side)
+      .await
+      .with_context(|| "unable to validate one-cancels-other order prices")?;
+    let () = validate_oco_prices(side, &price, take_profit, stop_loss)?;
+  }
+
+  let trailing = trail_price.is_some() || trail_percent.is_some();
+  let type_ = if oco {
+    // Alpaca expects one-cancels-other orders to be of type limit, with
+    // the actual prices being provided by the legs.
+    order::Type::Limit
+  } else {
+    determine_order_type(&limit_price, &stop_price, trailing)
+  };
+  let time_in_force = determine_time_in_force(time_in_force, fractional || notional)?;
   let take_profit = take_profit_price.map(order::TakeProfit::Limit);
   let stop_loss = match stop_loss_stop_price {
     Some(stop_price) => match stop_loss_limit_price {
@@ -997,44 +2780,682 @@ async fn order_submit(client: Client, submit: SubmitOrder) -> Result<()> {
     },
     None => None,
   };
-  let time_in_force = time_in_force.to_time_in_force();
 
-  // TODO: We should probably support other forms of specifying
-  //       the symbol.
-  let request = order::CreateReqInit {
-    class,
-    type_,
-    time_in_force,
-    limit_price,
-    stop_price,
-    take_profit,
-    stop_loss,
-    extended_hours,
-    ..Default::default()
+  // TODO: We should probably support other forms of specifying
+  //       the symbol.
+  let request = order::CreateReqInit {
+    class,
+    type_,
+    time_in_force,
+    limit_price,
+    stop_price,
+    trail_price,
+    trail_percent,
+    take_profit,
+    stop_loss,
+    extended_hours,
+    client_order_id: Some(client_order_id),
+    ..Default::default()
+  }
+  .init(symbol.clone(), side, amount);
+
+  let submission = Submission {
+    symbol,
+    value,
+    sizing,
+    request,
+  };
+  Ok(submission)
+}
+
+
+/// Submit an order.
+async fn order_submit(
+  client: Client,
+  submit: SubmitOrder,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
+  let wait = submit.wait;
+  let timeout = submit.timeout;
+  let submission = prepare_submission(&client, submit).await?;
+
+  if dry_run {
+    return DryRun::SubmitOrder {
+      request: &submission.request,
+    }
+    .print(output)
+  }
+
+  if confirm != Confirm::Never {
+    let summary = submission.summary(&client).await;
+    let () = confirm.ask("submit order", &summary)?;
+  }
+
+  if wait {
+    return order_submit_wait(client, &submission.request, timeout, output).await
+  }
+
+  let order = client
+    .issue::<order::Create>(&submission.request)
+    .await
+    .with_context(|| submit_error(&submission.request))?;
+
+  if output == Output::Json {
+    return print_json(&order)
+  }
+
+  println!("{} {}", order.id.as_hyphenated(), order.client_order_id);
+  for leg in order.legs {
+    println!("  {}", leg.id.as_hyphenated());
+  }
+  Ok(())
+}
+
+
+/// Create the context for an error submitting an order.
+///
+/// The submission may have reached Alpaca despite the error (e.g., on
+/// a timeout), so we include the client order ID to allow for checking.
+fn submit_error(request: &order::CreateReq) -> String {
+  match &request.client_order_id {
+    Some(id) => format!("failed to submit order with client order ID {id}"),
+    None => "failed to submit order".to_string(),
+  }
+}
+
+
+/// Check whether an order status indicates that the order is resting,
+/// i.e., waiting for its price target to be hit or for its parent to
+/// be filled.
+fn is_resting(status: order::Status) -> bool {
+  matches!(
+    status,
+    order::Status::New | order::Status::Accepted | order::Status::Held
+  )
+}
+
+
+/// Check whether an order is resting while waiting for its price
+/// target to be hit, as opposed to being about to be filled.
+fn is_waiting(order: &order::Order) -> bool {
+  order.type_ != order::Type::Market && is_resting(order.status)
+}
+
+
+/// Format the fill state of an order.
+fn format_fill(order: &order::Order, currency: &str) -> String {
+  format!(
+    "filled {filled} of {amount} @ {price}",
+    filled = order.filled_quantity,
+    amount = format_amount(&order.amount, currency),
+    price = format_option_price(&order.average_fill_price, currency),
+  )
+}
+
+
+/// Submit an order and wait for it to be filled (or canceled, expired,
+/// or rejected) and for its legs, if any, to be placed.
+async fn order_submit_wait(
+  client: Client,
+  request: &order::CreateReq,
+  timeout: Option<u64>,
+  output: Output,
+) -> Result<()> {
+  let deadline = timeout.map(|timeout| Instant::now() + StdDuration::from_secs(timeout));
+  // Subscribe to updates before submitting the order, so that we
+  // can't miss any fills.
+  let watcher = OrderWatcher::new(&client);
+  let currency = client.issue::<account::Get>(&());
+
+  let (mut watcher, currency) = join!(watcher, currency);
+  let currency = currency
+    .with_context(|| "failed to retrieve account information")?
+    .currency;
+
+  let order = client
+    .issue::<order::Create>(request)
+    .await
+    .with_context(|| submit_error(request))?;
+
+  if output == Output::Text {
+    println!("{} {}", order.id.as_hyphenated(), order.client_order_id);
+    for leg in &order.legs {
+      println!("  {}", leg.id.as_hyphenated());
+    }
+  }
+
+  let settled = |order: &order::Order| {
+    (is_terminal(order.status) || is_waiting(order))
+      && order
+        .legs
+        .iter()
+        .all(|leg| is_terminal(leg.status) || is_resting(leg.status))
+  };
+  let mut reported = HashMap::new();
+  let report = |order: &order::Order| {
+    let filled = reported.entry(order.id).or_insert_with(|| Num::from(0));
+    if order.filled_quantity > *filled {
+      *filled = order.filled_quantity.clone();
+      eprintln!(
+        "{} {}: {}",
+        order.symbol,
+        order.id.as_hyphenated(),
+        format_fill(order, &currency)
+      );
+    }
+  };
+
+  let order = watcher
+    .wait(&client, order, deadline, settled, report)
+    .await?;
+
+  if output == Output::Json {
+    let () = print_json(&order)?;
+  } else {
+    println!(
+      "{}: {}, {}",
+      order.id.as_hyphenated(),
+      format_order_status(order.status),
+      format_fill(&order, &currency)
+    );
+    for leg in &order.legs {
+      println!(
+        "  {}: {}, {}",
+        leg.id.as_hyphenated(),
+        format_order_status(leg.status),
+        format_fill(leg, &currency)
+      );
+    }
+  }
+
+  // An order waiting for its price target was placed successfully.
+  if !is_waiting(&order) {
+    let () = WaitError::check(order.status)?;
+  }
+  Ok(())
+}
+
+
+/// An order as specified in a batch file.
+///
+/// The fields correspond to the options of the `order submit`
+/// subcommand.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchOrder {
+  side: String,
+  symbol: String,
+  #[serde(default)]
+  quantity: Option<Num>,
+  #[serde(default)]
+  value: Option<Num>,
+  #[serde(default)]
+  percent_equity: Option<Num>,
+  #[serde(default)]
+  percent_buying_power: Option<Num>,
+  #[serde(default)]
+  risk: Option<String>,
+  #[serde(default)]
+  fractional: Option<bool>,
+  #[serde(default)]
+  notional: Option<bool>,
+  #[serde(default)]
+  limit_price: Option<Num>,
+  #[serde(default)]
+  stop_price: Option<Num>,
+  #[serde(default)]
+  trail_price: Option<Num>,
+  #[serde(default)]
+  trail_percent: Option<Num>,
+  #[serde(default)]
+  take_profit_price: Option<Num>,
+  #[serde(default)]
+  stop_loss_stop_price: Option<Num>,
+  #[serde(default)]
+  stop_loss_limit_price: Option<Num>,
+  #[serde(default)]
+  oco: Option<bool>,
+  #[serde(default)]
+  extended_hours: Option<bool>,
+  #[serde(default)]
+  time_in_force: Option<String>,
+  #[serde(default)]
+  client_order_id: Option<String>,
+}
+
+impl TryFrom<BatchOrder> for SubmitOrder {
+  type Error = Error;
+
+  /// Convert a `BatchOrder` into a `SubmitOrder`, performing the checks
+  /// that `clap` takes care of for orders provided on the command line.
+  fn try_from(order: BatchOrder) -> Result<Self> {
+    let BatchOrder {
+      side,
+      symbol,
+      quantity,
+      value,
+      percent_equity,
+      percent_buying_power,
+      risk,
+      fractional,
+      notional,
+      limit_price,
+      stop_price,
+      trail_price,
+      trail_percent,
+      take_profit_price,
+      stop_loss_stop_price,
+      stop_loss_limit_price,
+      oco,
+      extended_hours,
+      time_in_force,
+      client_order_id,
+    } = order;
+
+    let side = side.parse::<Side>().map_err(Error::msg)?;
+    let time_in_force = time_in_force
+      .as_deref()
+      .map(str::parse::<TimeInForce>)
+      .transpose()
+      .map_err(Error::msg)?;
+    let risk = risk
+      .as_deref()
+      .map(str::parse::<Risk>)
+      .transpose()
+      .map_err(Error::msg)?;
+    let oco = oco.unwrap_or(false);
+    let fractional = fractional.unwrap_or(false);
+    let notional = notional.unwrap_or(false);
+    let trailing = trail_price.is_some() || trail_percent.is_some();
+
+    let amounts = [
+      quantity.is_some(),
+      value.is_some(),
+      percent_equity.is_some(),
+      percent_buying_power.is_some(),
+      risk.is_some(),
+    ];
+    ensure!(
+      amounts.iter().filter(|present| **present).count() == 1,
+      "exactly one of `quantity`, `value`, `percent_equity`, `percent_buying_power`, \
+       and `risk` needs to be provided"
+    );
+    ensure!(
+      !fractional || !notional,
+      "`fractional` and `notional` are mutually exclusive"
+    );
+    ensure!(
+      !(fractional || notional) || (quantity.is_none() && risk.is_none()),
+      "`fractional` and `notional` require the amount to be given as a value"
+    );
+    ensure!(
+      trail_price.is_none() || trail_percent.is_none(),
+      "`trail_price` and `trail_percent` are mutually exclusive"
+    );
+    ensure!(
+      !trailing || (limit_price.is_none() && stop_price.is_none()),
+      "trailing stop orders cannot have a limit or stop price"
+    );
+    if oco {
+      ensure!(
+        take_profit_price.is_some() && stop_loss_stop_price.is_some(),
+        "one-cancels-other orders require `take_profit_price` and `stop_loss_stop_price`"
+      );
+      ensure!(
+        !trailing && limit_price.is_none() && stop_price.is_none(),
+        "one-cancels-other orders cannot have a limit, stop, or trail price"
+      );
+    }
+
+    let submit = SubmitOrder {
+      side,
+      symbol,
+      quantity,
+      value,
+      percent_equity,
+      percent_buying_power,
+      risk,
+      fractional,
+      notional,
+      limit_price,
+      stop_price,
+      trail_price,
+      trail_percent,
+      take_profit_price,
+      stop_loss_stop_price,
+      stop_loss_limit_price,
+      oco,
+      extended_hours: extended_hours.unwrap_or(false),
+      time_in_force,
+      client_order_id,
+      wait: false,
+      timeout: None,
+    };
+    Ok(submit)
+  }
+}
+
+
+/// Determine the format of a batch file based on its extension.
+fn infer_batch_format(path: &Path) -> Result<BatchFormat> {
+  let extension = path
+    .extension()
+    .and_then(OsStr::to_str)
+    .map(str::to_ascii_lowercase);
+  match extension.as_deref() {
+    Some("csv") => Ok(BatchFormat::Csv),
+    Some("json" | "jsonl" | "ndjson") => Ok(BatchFormat::Ndjson),
+    _ => bail!(
+      "unable to infer format of {}; please specify it via --format",
+      path.display()
+    ),
+  }
+}
+
+
+/// Parse the orders contained in a batch file.
+///
+/// Each order is reported alongside the line it was found on. Orders
+/// that are invalid are reported as errors.
+fn parse_batch_orders(
+  content: &str,
+  format: BatchFormat,
+) -> Result<Vec<(u64, Result<SubmitOrder>)>> {
+  let parse = |result: Result<BatchOrder>| result.and_then(SubmitOrder::try_from);
+
+  match format {
+    BatchFormat::Csv => {
+      let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+      let headers = reader
+        .headers()
+        .context("failed to read CSV header")?
+        .clone();
+
+      reader
+        .records()
+        .map(|result| {
+          let record = result.context("failed to read CSV record")?;
+          let line = record.position().map(csv::Position::line).unwrap_or(0);
+          let order = record
+            .deserialize::<BatchOrder>(Some(&headers))
+            .map_err(Error::from);
+          Ok((line, parse(order)))
+        })
+        .collect()
+    },
+    BatchFormat::Ndjson => {
+      let orders = content
+        .lines()
+        .zip(1..)
+        .filter(|(line, _)| !line.trim().is_empty())
+        .map(|(line, number)| {
+          let order = from_json::<BatchOrder>(line).map_err(Error::from);
+          (number, parse(order))
+        })
+        .collect();
+      Ok(orders)
+    },
+  }
+}
+
+
+/// The result of submitting a single order of a batch.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum BatchResult {
+  /// The order was submitted successfully.
+  Submitted {
+    line: u64,
+    id: order::Id,
+    client_order_id: String,
+  },
+  /// The submission failed.
+  Failed { line: u64, error: String },
+  /// The order was not submitted, because an earlier submission failed.
+  Skipped { line: u64 },
+}
+
+impl BatchResult {
+  /// Retrieve the line the order was found on.
+  fn line(&self) -> u64 {
+    match self {
+      Self::Submitted { line, .. } | Self::Failed { line, .. } | Self::Skipped { line } => *line,
+    }
+  }
+}
+
+
+/// Submit a batch of orders via `submit`, reporting the result of each
+/// ordered by line.
+///
+/// With `stop_on_error`, orders are submitted one after the other and
+/// the remaining ones are skipped once a submission failed. Otherwise
+/// up to `MAX_CONCURRENCY` orders are submitted at a time.
+async fn submit_batch<S, F>(
+  requests: Vec<(u64, order::CreateReq)>,
+  stop_on_error: bool,
+  submit: S,
+) -> Vec<BatchResult>
+where
+  S: Fn(order::CreateReq) -> F,
+  F: Future<Output = Result<order::Order>>,
+{
+  // Skipping only works if we don't start submitting an order before
+  // the previous submission concluded.
+  let concurrency = if stop_on_error { 1 } else { MAX_CONCURRENCY };
+  let failed = Cell::new(false);
+  let mut results = iter(requests)
+    .map(|(line, request)| {
+      let submit = &submit;
+      let failed = &failed;
+      async move {
+        if stop_on_error && failed.get() {
+          return BatchResult::Skipped { line }
+        }
+
+        let context = submit_error(&request);
+        match submit(request).await {
+          Ok(order) => BatchResult::Submitted {
+            line,
+            id: order.id,
+            client_order_id: order.client_order_id,
+          },
+          Err(err) => {
+            let () = failed.set(true);
+            let error = err.context(context);
+            BatchResult::Failed {
+              line,
+              error: format!("{error:#}"),
+            }
+          },
+        }
+      }
+    })
+    .buffer_unordered(concurrency)
+    .collect::<Vec<_>>()
+    .await;
+  let () = results.sort_by_key(BatchResult::line);
+  results
+}
+
+
+/// Submit a batch of orders read from a file.
+async fn order_submit_batch(
+  client: Client,
+  batch: SubmitBatch,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
+  let SubmitBatch {
+    path,
+    format,
+    stop_on_error,
+  } = batch;
+
+  let format = match format {
+    Some(format) => format,
+    None => infer_batch_format(&path)?,
+  };
+  let content =
+    read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+  let orders = parse_batch_orders(&content, format)
+    .with_context(|| format!("failed to parse {}", path.display()))?;
+
+  // We validate all orders up front and only submit any of them if all
+  // are valid, in order to not end up with a partially submitted batch
+  // because of a typo somewhere.
+  let submissions = iter(orders)
+    .map(|(line, result)| {
+      let client = &client;
+      async move {
+        let result = match result {
+          Ok(submit) => prepare_submission(client, submit).await,
+          Err(err) => Err(err),
+        };
+        (line, result)
+      }
+    })
+    .buffered(MAX_CONCURRENCY)
+    .collect::<Vec<_>>()
+    .await;
+
+  let mut invalid = 0;
+  for (line, result) in &submissions {
+    if let Err(err) = result {
+      eprintln!("{}:{line}: {err:#}", path.display());
+      invalid += 1;
+    }
   }
-  .init(symbol, side, order::Amount::quantity(quantity));
+  ensure!(
+    invalid == 0,
+    "{invalid} of {} order(s) in {} are invalid; nothing was submitted",
+    submissions.len(),
+    path.display()
+  );
 
-  let order = client
-    .issue::<order::Create>(&request)
-    .await
-    .with_context(|| "failed to submit order")?;
+  // SANITY: We checked that all submissions are valid above.
+  let submissions = submissions
+    .into_iter()
+    .map(|(line, result)| (line, result.unwrap()))
+    .collect::<Vec<_>>();
 
-  println!("{}", order.id.as_hyphenated());
-  for leg in order.legs {
-    println!("  {}", leg.id.as_hyphenated());
+  if dry_run {
+    let requests = submissions
+      .iter()
+      .map(|(_, submission)| &submission.request)
+      .collect();
+    return DryRun::SubmitOrders { requests }.print(output)
+  }
+
+  if confirm != Confirm::Never && !submissions.is_empty() {
+    let summaries = iter(&submissions)
+      .map(|(_, submission)| submission.summary(&client))
+      .buffered(MAX_CONCURRENCY)
+      .collect::<Vec<_>>()
+      .await;
+    let summary = summaries.join("\n\n");
+    let action = format!("submit {} order(s)", submissions.len());
+    let () = confirm.ask(&action, &summary)?;
+  }
+
+  let requests = submissions
+    .into_iter()
+    .map(|(line, submission)| (line, submission.request))
+    .collect();
+  let results = submit_batch(requests, stop_on_error, |request| {
+    let client = &client;
+    async move {
+      let order = client.issue::<order::Create>(&request).await?;
+      Ok(order)
+    }
+  })
+  .await;
+
+  if output == Output::Json {
+    let () = print_json(&results)?;
+  } else {
+    let line_max = max_width(&results, |result| result.line().to_string().len());
+    for result in &results {
+      let line = result.line();
+      match result {
+        BatchResult::Submitted {
+          id,
+          client_order_id,
+          ..
+        } => println!("{line:>line_max$} {} {client_order_id}", id.as_hyphenated()),
+        BatchResult::Failed { error, .. } => println!("{line:>line_max$} error: {error}"),
+        BatchResult::Skipped { .. } => println!("{line:>line_max$} skipped"),
+      }
+    }
   }
+
+  let unsuccessful = results
+    .iter()
+    .filter(|result| !matches!(result, BatchResult::Submitted { .. }))
+    .count();
+  ensure!(
+    unsuccessful == 0,
+    "{unsuccessful} of {} order(s) were not submitted",
+    results.len()
+  );
   Ok(())
 }
 
 
+/// Determine the trail to set when changing a trailing stop order.
+///
+/// The stop price of a trailing stop order is derived from the high
+/// water mark by Alpaca and cannot be set directly, nor does such an
+/// order have a limit price. Only the trail itself can be changed and
+/// it has to stay of the same kind.
+fn trailing_stop_change(
+  order: &order::Order,
+  limit_price: Option<&Num>,
+  stop_price: Option<&Num>,
+  trail_price: Option<Num>,
+  trail_percent: Option<Num>,
+) -> Result<Option<Num>> {
+  if limit_price.is_some() || stop_price.is_some() {
+    bail!(
+      "order {} is a trailing stop order; only --trail-price/--trail-percent can be changed",
+      order.id.as_hyphenated()
+    )
+  }
+
+  let trail = match (trail_price, trail_percent) {
+    (Some(_), None) if order.trail_price.is_none() => {
+      bail!("order trails by percentage; use --trail-percent to change it")
+    },
+    (None, Some(_)) if order.trail_percent.is_none() => {
+      bail!("order trails by price; use --trail-price to change it")
+    },
+    (trail_price, trail_percent) => trail_price.or(trail_percent),
+  };
+  Ok(trail)
+}
+
+
 /// Change an order.
-async fn order_change(client: Client, change: ChangeOrder) -> Result<()> {
+async fn order_change(
+  client: Client,
+  change: ChangeOrder,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
   let ChangeOrder {
     id,
     quantity,
     value,
     limit_price,
     stop_price,
+    trail_price,
+    trail_percent,
     time_in_force,
   } = change;
 
@@ -1044,8 +3465,24 @@ async fn order_change(client: Client, change: ChangeOrder) -> Result<()> {
     .with_context(|| format!("failed to retrieve order {}", id.0.as_hyphenated()))?;
 
   let time_in_force = time_in_force.map(|x| x.to_time_in_force());
+  let (stop_price, trail) = if order.type_ == order::Type::TrailingStop {
+    let trail = trailing_stop_change(
+      &order,
+      limit_price.as_ref(),
+      stop_price.as_ref(),
+      trail_price,
+      trail_percent,
+    )?;
+    (None, trail)
+  } else {
+    ensure!(
+      trail_price.is_none() && trail_percent.is_none(),
+      "order {} is not a trailing stop order",
+      id.0.as_hyphenated()
+    );
+    (stop_price.or_else(|| order.stop_price.take()), None)
+  };
   let limit_price = limit_price.or_else(|| order.limit_price.take());
-  let stop_price = stop_price.or_else(|| order.stop_price.take());
 
   let quantity = match (quantity, value) {
     (None, None) => {
@@ -1081,73 +3518,301 @@ async fn order_change(client: Client, change: ChangeOrder) -> Result<()> {
     time_in_force,
     limit_price,
     stop_price,
+    trail,
     ..Default::default()
   };
 
+  if dry_run {
+    return DryRun::ChangeOrder {
+      id: id.0,
+      request: &request,
+    }
+    .print(output)
+  }
+
+  if confirm != Confirm::Never {
+    // SANITY: Quantity is always set at this point, as notional orders
+    //         are rejected above.
+    let quantity = request.quantity.as_ref().unwrap();
+    let price = request.limit_price.as_ref().or(request.stop_price.as_ref());
+    let notional = estimate_notional(&client, &order.symbol, order.side, quantity, price).await;
+    let summary = format_order_summary(
+      &order.symbol,
+      order.side,
+      Some(quantity),
+      notional.as_ref(),
+      order.type_,
+      request.time_in_force.unwrap_or(order.time_in_force),
+    );
+    let () = confirm.ask(&format!("change order {}", id.0.as_hyphenated()), &summary)?;
+  }
+
   let order = client
     .issue::<order::Change>(&(id.0, request))
     .await
     .with_context(|| format!("failed to change order {}", id.0.as_hyphenated()))?;
 
+  if output == Output::Json {
+    return print_json(&order)
+  }
+
   println!("{}", order.id.as_hyphenated());
   Ok(())
 }
 
 
-/// Cancel an open order.
-async fn order_cancel(client: Client, cancel: CancelOrder) -> Result<()> {
-  match cancel {
-    CancelOrder::ById(id) => client
-      .issue::<order::Delete>(&id.0)
-      .await
-      .with_context(|| "failed to cancel order"),
-    CancelOrder::All => {
-      // TODO: This isn't quite sufficient if there are more than 500
-      //       open orders (unlikely but possible).
-      let request = orders::ListReq {
-        status: orders::Status::Open,
-        limit: Some(500),
-        // No need to retrieve nested orders here, they should be
-        // canceled automatically when the "parent" is canceled.
-        nested: false,
-        ..Default::default()
-      };
-      let orders = client
-        .issue::<orders::List>(&request)
-        .await
-        .with_context(|| "failed to list orders")?;
+/// Check whether an order matches the filters provided for its
+/// cancellation.
+fn matches_cancel_filters(order: &order::Order, cancel: &CancelOrders, now: DateTime<Utc>) -> bool {
+  let CancelOrders {
+    ids: _,
+    symbols,
+    side,
+    type_,
+    older_than,
+  } = cancel;
+
+  let symbol = symbols.is_empty() || symbols.contains(&order.symbol);
+  let side = side.as_ref().map_or(true, |side| {
+    let side = match side {
+      Side::Buy => order::Side::Buy,
+      Side::Sell => order::Side::Sell,
+    };
+    side == order.side
+  });
+  let type_ = type_.map_or(true, |type_| type_.to_order_type() == order.type_);
+  let age = older_than.map_or(true, |older_than| {
+    // Durations of unrepresentable size are way past any submission
+    // time.
+    Duration::from_std(older_than)
+      .ok()
+      .and_then(|older_than| now.checked_sub_signed(older_than))
+      .is_some_and(|time| submission_time(order) < time)
+  });
 
-      orders
-        .into_iter()
-        .map(|order| {
-          let id = order.id;
-          client.issue::<order::Delete>(&id).map_err(move |e| {
-            let id = order.id.as_hyphenated();
-            Error::new(e).context(format!("failed to cancel order {id}"))
-          })
-        })
-        .collect::<FuturesUnordered<_>>()
-        .try_for_each_concurrent(Some(MAX_CONCURRENCY), |()| ready(Ok(())))
-        .await
+  symbol && side && type_ && age
+}
+
+
+/// Cancel orders.
+async fn order_cancel(
+  client: Client,
+  cancel: CancelOrders,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
+  let all = cancel.ids.iter().any(|id| id == "all");
+  let orders = if cancel.ids.is_empty() || all {
+    ensure!(
+      cancel.ids.len() <= 1,
+      "'all' cannot be combined with order IDs"
+    );
+
+    let request = endpoint::ListOrdersReq {
+      status: orders::Status::Open,
+      // No need to retrieve nested orders here, they should be
+      // canceled automatically when the "parent" is canceled.
+      nested: false,
+      ..Default::default()
+    };
+    list_orders(&client, request, None).await?
+  } else {
+    iter(&cancel.ids)
+      .map(|id| get_order(&client, id))
+      .buffered(MAX_CONCURRENCY)
+      .try_collect::<Vec<_>>()
+      .await?
+  };
+
+  let now = Utc::now();
+  let orders = orders
+    .iter()
+    .filter(|order| matches_cancel_filters(order, &cancel, now))
+    .collect::<Vec<_>>();
+
+  if dry_run {
+    return DryRun::CancelOrders { orders }.print(output)
+  }
+
+  if confirm != Confirm::Never && !orders.is_empty() {
+    let summaries = iter(&orders)
+      .map(|order| format_cancel_summary(&client, order))
+      .buffered(MAX_CONCURRENCY)
+      .collect::<Vec<_>>()
+      .await;
+    let summary = summaries.join("\n\n");
+    let action = match orders.as_slice() {
+      [order] => format!("cancel order {}", order.id.as_hyphenated()),
+      orders => format!("cancel {} order(s)", orders.len()),
+    };
+    let () = confirm.ask(&action, &summary)?;
+  }
+
+  orders
+    .into_iter()
+    .map(|order| {
+      let id = order.id;
+      client.issue::<order::Delete>(&id).map_err(move |e| {
+        let id = id.as_hyphenated();
+        Error::new(e).context(format!("failed to cancel order {id}"))
+      })
+    })
+    .collect::<FuturesUnordered<_>>()
+    .try_for_each_concurrent(Some(MAX_CONCURRENCY), |()| ready(Ok(())))
+    .await
+}
+
+
+/// Format the summary of an order that is about to be canceled.
+async fn format_cancel_summary(client: &Client, order: &order::Order) -> String {
+  let (quantity, notional) = match &order.amount {
+    order::Amount::Quantity { quantity } => {
+      let price = order.limit_price.as_ref().or(order.stop_price.as_ref());
+      let notional = estimate_notional(client, &order.symbol, order.side, quantity, price).await;
+      (Some(quantity), notional)
     },
+    order::Amount::Notional { notional } => (None, Some(notional.clone())),
+  };
+  let summary = format_order_summary(
+    &order.symbol,
+    order.side,
+    quantity,
+    notional.as_ref(),
+    order.type_,
+    order.time_in_force,
+  );
+  format!(
+    "  order id:         {}\n{summary}",
+    order.id.as_hyphenated()
+  )
+}
+
+
+/// Retrieve an order by its ID or, failing that, by its client order
+/// ID.
+async fn get_order(client: &Client, id: &str) -> Result<order::Order> {
+  // Client order IDs are free form and can very well be UUIDs
+  // themselves (ours are by default). Hence, we can't tell from the
+  // format alone what kind of ID we are dealing with.
+  if let Ok(uuid) = Uuid::parse_str(id) {
+    match client.issue::<order::Get>(&order::Id(uuid)).await {
+      Ok(order) => return Ok(order),
+      Err(RequestError::Endpoint(order::GetError::NotFound(..))) => (),
+      Err(err) => return Err(Error::new(err).context(format!("failed to retrieve order {id}"))),
+    }
+  }
+
+  client
+    .issue::<order::GetByClientId>(&id.to_string())
+    .await
+    .with_context(|| format!("failed to retrieve order {id}"))
+}
+
+
+/// Retrieve the time at which an order was submitted, for the purpose
+/// of paginating through orders.
+fn submission_time(order: &order::Order) -> DateTime<Utc> {
+  order.submitted_at.unwrap_or(order.created_at)
+}
+
+
+/// Retrieve the orders matching the given request, paginating through
+/// the results as necessary.
+///
+/// At most `limit` orders are retrieved, if provided.
+async fn list_orders(
+  client: &Client,
+  mut request: endpoint::ListOrdersReq,
+  limit: Option<usize>,
+) -> Result<Vec<order::Order>> {
+  let mut orders = Vec::new();
+  let mut seen = HashSet::new();
+
+  loop {
+    let page_size = match limit {
+      Some(limit) => min(limit - orders.len(), MAX_ORDERS_PER_PAGE),
+      None => MAX_ORDERS_PER_PAGE,
+    };
+    if page_size == 0 {
+      break
+    }
+
+    request.limit = Some(page_size);
+    let page = client
+      .issue::<endpoint::ListOrders>(&request)
+      .await
+      .with_context(|| "failed to list orders")?;
+
+    let exhausted = page.len() < page_size;
+    let last = page.last().map(submission_time);
+    let count = orders.len();
+
+    for order in page {
+      if seen.insert(order.id) {
+        let () = orders.push(order);
+      }
+    }
+
+    if exhausted {
+      break
+    }
+
+    // If a page did not contain any new orders, we are stuck (because
+    // more orders than fit on a page share the same submission time)
+    // and have to give up.
+    if orders.len() == count {
+      warn!(
+        "unable to page past orders sharing the same submission time; \
+         order list is incomplete ({} orders retrieved)",
+        orders.len()
+      );
+      break
+    }
+
+    // SANITY: The page is not empty or we would have bailed above.
+    let last = last.unwrap();
+    // Time bounds are exclusive, but other orders may have been
+    // submitted at the exact same time as the last one we got. So we
+    // include said time in the next request and weed out duplicates.
+    match request.direction {
+      endpoint::Direction::Descending => {
+        request.until = Some(last + Duration::microseconds(1));
+      },
+      endpoint::Direction::Ascending => {
+        request.after = Some(last - Duration::microseconds(1));
+      },
+    }
   }
+
+  Ok(orders)
 }
 
 
 /// Retrieve information about an order.
-async fn order_get(client: Client, id: OrderId) -> Result<()> {
+async fn order_get(client: Client, id: String, output: Output) -> Result<()> {
   let currency = client.issue::<account::Get>(&());
-  let order = client.issue::<order::Get>(&id.0);
+  let order = get_order(&client, &id);
 
   let (currency, order) = join!(currency, order);
   let currency = currency
     .with_context(|| "failed to retrieve account information")?
     .currency;
 
-  let order = order.with_context(|| "failed to retrieve order information")?;
+  let order = order?;
+  order_print_details(&order, &currency, output)
+}
+
+
+/// Print detailed information about an order.
+fn order_print_details(order: &order::Order, currency: &str, output: Output) -> Result<()> {
+  if output == Output::Json {
+    return print_json(order)
+  }
+
   let legs = order
     .legs
-    .into_iter()
+    .iter()
     .map(|order| order.id.as_hyphenated().to_string())
     .collect::<Vec<_>>()
     .join(",");
@@ -1155,6 +3820,7 @@ async fn order_get(client: Client, id: OrderId) -> Result<()> {
   println!(
     r#"{sym}:
   order id:         {id}
+  client order id:  {client_id}
   status:           {status}
   created at:       {created}
   submitted at:     {submitted}
@@ -1169,10 +3835,13 @@ async fn order_get(client: Client, id: OrderId) -> Result<()> {
   good until:       {good_until}
   limit:            {limit}
   stop:             {stop}
+  trail:            {trail}
+  high water mark:  {hwm}
   extended hours:   {extended_hours}
   legs:             {legs}"#,
     sym = order.symbol,
     id = order.id.as_hyphenated(),
+    client_id = order.client_order_id,
     status = format_order_status(order.status),
     created = format_local_time(order.created_at),
     submitted = order
@@ -1196,12 +3865,14 @@ async fn order_get(client: Client, id: OrderId) -> Result<()> {
       .map(format_local_time)
       .unwrap_or_else(|| "N/A".into()),
     amount_type = format_amount_type(&order.amount).to_string() + ":",
-    amount = format_amount(&order.amount, &currency),
+    amount = format_amount(&order.amount, currency),
     filled_qty = order.filled_quantity,
     type_ = format_order_type(order.type_),
     side = format_order_side(order.side),
-    limit = format_option_price(&order.limit_price, &currency),
-    stop = format_option_price(&order.stop_price, &currency),
+    limit = format_option_price(&order.limit_price, currency),
+    stop = format_option_price(&order.stop_price, currency),
+    trail = format_trail(order, currency),
+    hwm = format_option_price(&high_water_mark(order), currency),
     good_until = format_time_in_force(order.time_in_force),
     extended_hours = order.extended_hours,
     legs = if !legs.is_empty() { legs } else { "N/A".into() },
@@ -1210,6 +3881,232 @@ async fn order_get(client: Client, id: OrderId) -> Result<()> {
 }
 
 
+/// The interval at which to poll the state of an order while waiting
+/// for it to reach a terminal state.
+const ORDER_POLL_INTERVAL: StdDuration = StdDuration::from_secs(5);
+
+
+/// Check whether an order status is terminal, i.e., whether the order
+/// is done for good.
+fn is_terminal(status: order::Status) -> bool {
+  matches!(
+    status,
+    order::Status::Filled
+      | order::Status::Canceled
+      | order::Status::Expired
+      | order::Status::Rejected
+      | order::Status::Replaced
+  )
+}
+
+
+/// A type helping with waiting for orders to reach a terminal state.
+///
+/// Order updates are streamed, if possible. In addition, the state of
+/// an order is polled periodically, in case the stream fails or we
+/// could not subscribe to it in the first place.
+struct OrderWatcher {
+  /// The stream of order updates along with the subscription keeping
+  /// it alive, if we are subscribed.
+  updates: Option<(
+    <updates::OrderUpdates as Subscribable>::Stream,
+    <updates::OrderUpdates as Subscribable>::Subscription,
+  )>,
+}
+
+impl OrderWatcher {
+  /// Subscribe to order updates.
+  ///
+  /// Orders should be retrieved only after the watcher got created, so
+  /// that no update can be missed.
+  async fn new(client: &Client) -> Self {
+    let updates = client
+      .subscribe::<updates::OrderUpdates>()
+      .await
+      .map_err(|err| warn!("failed to subscribe to trade updates; falling back to polling: {err}"))
+      .ok();
+
+    Self { updates }
+  }
+
+  /// Wait for an order (along with its legs) to settle, as determined
+  /// by `settled`, or for the deadline, if any, to pass, whichever
+  /// happens first. Every state of the order or one of its legs that
+  /// we learn about is reported to `report`. The most recent state of
+  /// the order is returned.
+  async fn wait<S, R>(
+    &mut self,
+    client: &Client,
+    mut order: order::Order,
+    deadline: Option<Instant>,
+    settled: S,
+    mut report: R,
+  ) -> Result<order::Order>
+  where
+    S: Fn(&order::Order) -> bool,
+    R: FnMut(&order::Order),
+  {
+    let mut next_poll = Instant::now() + ORDER_POLL_INTERVAL;
+
+    loop {
+      if settled(&order) || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        break Ok(order)
+      }
+
+      let until = deadline.map_or(next_poll, |deadline| min(deadline, next_poll));
+      let update = async {
+        match &mut self.updates {
+          Some((updates, _subscription)) => updates.next().await,
+          None => pending().await,
+        }
+      };
+
+      match timeout_at(until, update).await {
+        Ok(Some(Ok(Ok(update)))) => {
+          if update.order.id == order.id {
+            let () = report(&update.order);
+            // Updates may not include legs, but those don't go away.
+            let legs = take(&mut order.legs);
+            order = update.order;
+            if order.legs.is_empty() {
+              order.legs = legs;
+            }
+          } else if let Some(leg) = order.legs.iter_mut().find(|leg| leg.id == update.order.id) {
+            let () = report(&update.order);
+            *leg = update.order;
+          }
+        },
+        Ok(Some(Ok(Err(err)))) => warn!("failed to parse trade update: {err}"),
+        Ok(Some(Err(err))) => {
+          warn!("trade update stream failed; falling back to polling: {err}");
+          self.updates = None;
+        },
+        Ok(None) => {
+          warn!("trade update stream ended; falling back to polling");
+          self.updates = None;
+        },
+        // Either it is time to poll or the deadline passed. In the
+        // latter case we still retrieve the order one last time, to
+        // report its most recent state.
+        Err(_elapsed) => {
+          let parent = poll_order(client, order.id);
+          let legs = order
+            .legs
+            .iter()
+            .map(|leg| poll_order(client, leg.id))
+            .collect::<FuturesOrdered<_>>()
+            .try_collect::<Vec<_>>();
+
+          let (parent, legs) = join!(parent, legs);
+          order = parent?;
+          order.legs = legs?;
+
+          let () = report(&order);
+          let () = order.legs.iter().for_each(&mut report);
+          next_poll = Instant::now() + ORDER_POLL_INTERVAL;
+        },
+      }
+    }
+  }
+}
+
+
+/// Retrieve the current state of an order.
+async fn poll_order(client: &Client, id: order::Id) -> Result<order::Order> {
+  client
+    .issue::<order::Get>(&id)
+    .await
+    .with_context(|| format!("failed to retrieve order {}", id.as_hyphenated()))
+}
+
+
+/// An error indicating that an order we waited for did not get filled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WaitError {
+  /// The order was canceled (or replaced).
+  Canceled,
+  /// The order expired.
+  Expired,
+  /// The order was rejected.
+  Rejected,
+  /// The order did not reach a terminal state in time.
+  Timeout,
+}
+
+impl WaitError {
+  /// Check the status of an order we waited for, reporting an error
+  /// if it did not get filled.
+  fn check(status: order::Status) -> Result<(), Self> {
+    let error = match status {
+      order::Status::Filled => return Ok(()),
+      order::Status::Canceled | order::Status::Replaced => Self::Canceled,
+      order::Status::Expired => Self::Expired,
+      order::Status::Rejected => Self::Rejected,
+      _ => Self::Timeout,
+    };
+    Err(error)
+  }
+
+  /// Retrieve the numeric exit code the program should report.
+  fn code(self) -> u8 {
+    match self {
+      Self::Canceled => 3,
+      Self::Expired => 4,
+      Self::Rejected => 5,
+      Self::Timeout => 6,
+    }
+  }
+
+  /// Retrieve the exit code the program should report.
+  fn exit_code(self) -> ExitCode {
+    ExitCode::from(self.code())
+  }
+}
+
+impl Display for WaitError {
+  fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+    let message = match self {
+      Self::Canceled => "order was canceled",
+      Self::Expired => "order expired",
+      Self::Rejected => "order was rejected",
+      Self::Timeout => "timed out waiting for order to reach a terminal state",
+    };
+    fmt.write_str(message)
+  }
+}
+
+impl StdError for WaitError {}
+
+
+/// Wait for an order to reach a terminal state and print it.
+async fn order_wait(
+  client: Client,
+  id: String,
+  timeout: Option<u64>,
+  output: Output,
+) -> Result<()> {
+  let deadline = timeout.map(|timeout| Instant::now() + StdDuration::from_secs(timeout));
+  let mut watcher = OrderWatcher::new(&client).await;
+
+  let currency = client.issue::<account::Get>(&());
+  let order = get_order(&client, &id);
+
+  let (currency, order) = join!(currency, order);
+  let currency = currency
+    .with_context(|| "failed to retrieve account information")?
+    .currency;
+
+  let settled = |order: &order::Order| is_terminal(order.status);
+  let order = watcher
+    .wait(&client, order?, deadline, settled, |_| ())
+    .await?;
+  let () = order_print_details(&order, &currency, output)?;
+
+  let () = WaitError::check(order.status)?;
+  Ok(())
+}
+
+
 /// Determine the maximum width of values produced by applying a
 /// function on each element of a slice.
 fn max_width<T, F>(slice: &[T], f: F) -> usize
@@ -1219,10 +4116,55 @@ where
   slice.iter().fold(0, |m, i| max(m, f(i)))
 }
 
+/// Format the amount by which the stop price of a trailing stop order
+/// trails the market.
+fn format_trail(order: &order::Order, currency: &str) -> Str {
+  match (&order.trail_price, &order.trail_percent) {
+    (Some(price), _) => format_price(price, currency),
+    (None, Some(percent)) => format!("{percent:.2}%").into(),
+    (None, None) => "N/A".into(),
+  }
+}
+
+
+/// Determine the high water mark of a trailing stop order.
+///
+/// Alpaca reports the high water mark alongside the order, but it is
+/// not exposed by `apca`. However, the stop price is derived from it
+/// and so we can calculate it based on the stop price and the trail.
+/// For buy orders the "high" water mark is the lowest price seen.
+fn high_water_mark(order: &order::Order) -> Option<Num> {
+  if order.type_ != order::Type::TrailingStop {
+    return None
+  }
+
+  let stop = order.stop_price.as_ref()?;
+  let hwm = match (&order.trail_price, &order.trail_percent) {
+    (Some(price), _) => match order.side {
+      order::Side::Buy => stop - price,
+      order::Side::Sell => stop + price,
+    },
+    (None, Some(percent)) => {
+      let ratio = percent / Num::from(100);
+      let factor = match order.side {
+        order::Side::Buy => Num::from(1) + ratio,
+        order::Side::Sell => Num::from(1) - ratio,
+      };
+      if factor.is_zero() {
+        return None
+      }
+      stop / factor
+    },
+    (None, None) => return None,
+  };
+  Some(hwm)
+}
+
+
 /// Print details of an order.
 fn order_print(
   order: &order::Order,
-  quantity: &Num,
+  amount: &order::Amount,
   indent: &str,
   currency: &str,
   side_max: usize,
@@ -1230,15 +4172,30 @@ fn order_print(
   sym_max: usize,
 ) -> Result<()> {
   let time_in_force = format_time_in_force_short(order.time_in_force);
+  // The value of a notional order is known upfront, while for others
+  // we derive it from the price.
+  let total = |price: &Num| match amount {
+    order::Amount::Quantity { quantity } => format_price(&(price * quantity), currency),
+    order::Amount::Notional { notional } => format_price(notional, currency),
+  };
 
   let summary = match (&order.limit_price, &order.stop_price) {
+    (None, Some(stop)) if order.type_ == order::Type::TrailingStop => {
+      format!(
+        "trailing stop {} @ {} (hwm {}) = {}",
+        format_trail(order, currency),
+        format_price(stop, currency),
+        format_option_price(&high_water_mark(order), currency),
+        total(stop)
+      )
+    },
     (Some(limit), Some(stop)) => {
       debug_assert!(order.type_ == order::Type::StopLimit, "{:?}", order.type_);
       format!(
         "stop @ {}, limit @ {} = {}",
         format_price(stop, currency),
         format_price(limit, currency),
-        format_price(&(limit * quantity), currency)
+        total(limit)
       )
     },
     (Some(limit), None) => {
@@ -1246,16 +4203,17 @@ fn order_print(
       format!(
         "limit @ {} = {}",
         format_price(limit, currency),
-        format_price(&(limit * quantity), currency)
+        total(limit)
       )
     },
     (None, Some(stop)) => {
       debug_assert!(order.type_ == order::Type::Stop, "{:?}", order.type_);
-      format!(
-        "stop @ {} = {}",
-        format_price(stop, currency),
-        format_price(&(stop * quantity), currency)
-      )
+      format!("stop @ {} = {}", format_price(stop, currency), total(stop))
+    },
+    (None, None) if order.type_ == order::Type::TrailingStop => {
+      // The stop price of a trailing stop order may not be known yet
+      // while the order is not active.
+      format!("trailing stop {}", format_trail(order, currency))
     },
     (None, None) => {
       debug_assert!(order.type_ == order::Type::Market, "{:?}", order.type_);
@@ -1271,7 +4229,7 @@ fn order_print(
     side_width = side_max,
     side = format_order_side(order.side),
     qty_width = qty_max,
-    qty = format_approximate_quantity(quantity),
+    qty = format_list_amount(amount, currency),
     sym_width = sym_max,
     sym = order.symbol,
     summary = summary,
@@ -1295,95 +4253,89 @@ fn format_approximate_quantity(quantity: &Num) -> String {
   (if &rounded != quantity { "~" } else { "" }).to_string() + &rounded.to_string()
 }
 
-/// Retrieve or infer the quantity of an order.
-fn order_quantity<'client>(
-  client: &'client Client,
-  order: &order::Order,
-) -> impl Future<Output = Result<Num, Error>> + 'client {
-  let id = order.id;
-  let symbol = order.symbol.clone();
-  let amount = order.amount.clone();
-  let side = order.side;
-  let limit_price = order.limit_price.clone();
-
-  async move {
-    match amount {
-      order::Amount::Quantity { quantity } => Ok(quantity),
-      order::Amount::Notional { notional } => {
-        value_to_quantity(client, &symbol, side, &notional, limit_price)
-          .await
-          .with_context(|| {
-            format!(
-              "failed to estimate quantity for order {}",
-              id.as_hyphenated()
-            )
-          })
-      },
-    }
+/// Format the amount of an order for display in a list: notional
+/// orders are shown with their value, all others with their quantity.
+fn format_list_amount(amount: &order::Amount, currency: &str) -> String {
+  match amount {
+    order::Amount::Quantity { quantity } => format_approximate_quantity(quantity),
+    order::Amount::Notional { notional } => format_price(notional, currency).to_string(),
   }
 }
 
-/// List all currently open orders.
-async fn order_list(client: Client, closed: bool) -> Result<()> {
-  let request = orders::ListReq {
-    status: if closed {
-      orders::Status::Closed
-    } else {
-      orders::Status::Open
-    },
-    limit: Some(500),
+/// List open, closed, or all orders, optionally filtered.
+async fn order_list(client: Client, list: ListOrders, output: Output) -> Result<()> {
+  let ListOrders {
+    closed,
+    all,
+    symbols,
+    side,
+    after,
+    until,
+    direction,
+    limit,
+  } = list;
+
+  let status = if all {
+    orders::Status::All
+  } else if closed {
+    orders::Status::Closed
+  } else {
+    orders::Status::Open
+  };
+  let after = after
+    .map(|after| {
+      new_york_to_utc(&after)
+        .ok_or_else(|| anyhow!("cannot work with invalid/ambiguous after time"))
+    })
+    .transpose()?;
+  let until = until
+    .map(|until| {
+      new_york_to_utc(&until)
+        .ok_or_else(|| anyhow!("cannot work with invalid/ambiguous until time"))
+    })
+    .transpose()?;
+  let direction = match direction {
+    Direction::Asc => endpoint::Direction::Ascending,
+    Direction::Desc => endpoint::Direction::Descending,
+  };
+  let side = side.map(|side| match side {
+    Side::Buy => order::Side::Buy,
+    Side::Sell => order::Side::Sell,
+  });
+
+  let request = endpoint::ListOrdersReq {
+    status,
+    after,
+    until,
+    direction,
     nested: true,
+    symbols,
+    side,
     ..Default::default()
   };
 
   let currency = client.issue::<account::Get>(&());
-  let orders = client.issue::<orders::List>(&request);
+  let orders = list_orders(&client, request, limit);
 
   let (currency, orders) = join!(currency, orders);
   let currency = currency
     .with_context(|| "failed to retrieve account information")?
     .currency;
 
-  let orders = orders.with_context(|| "failed to list orders")?;
-  let count = orders.len();
-  // Associate a quantity with each order. That's mostly necessary to
-  // properly handle the `Amount` type properly that orders use. For
-  // most orders that is a trivial operation in which we only access and
-  // clone a member. But for notional orders we may end up inquiring the
-  // most recent quote in order to estimate the purchasable quantity.
-  // Because we reference this quantity multiple times moving forward,
-  // we basically cache it here, by pairing it up with the actual order
-  // object.
-  // The lint seems to be a false-positive.
-  #[allow(clippy::manual_try_fold)]
-  let orders = orders
-    .into_iter()
-    .map(|order| {
-      let future = order_quantity(&client, &order);
-      join(ready(order), future)
-    })
-    .collect::<FuturesOrdered<_>>()
-    .fold(
-      Ok(Vec::with_capacity(count)),
-      |acc, (order, result)| async {
-        acc.and_then(|mut vec| {
-          result.map(|data| {
-            vec.push((order, data));
-            vec
-          })
-        })
-      },
-    )
-    .await?;
+  let orders = orders?;
+  if output == Output::Json {
+    return print_json(&orders)
+  }
 
-  let side_max = max_width(&orders, |o| format_order_side(o.0.side).len());
-  let qty_max = max_width(&orders, |o| format_approximate_quantity(&o.1).len());
-  let sym_max = max_width(&orders, |o| o.0.symbol.len());
+  let side_max = max_width(&orders, |o| format_order_side(o.side).len());
+  let qty_max = max_width(&orders, |o| format_list_amount(&o.amount, &currency).len());
+  let sym_max = max_width(&orders, |o| o.symbol.len());
 
-  for (order, quantity) in orders {
-    order_print(&order, &quantity, "", &currency, side_max, qty_max, sym_max)?;
-    for leg in order.legs {
-      order_print(&leg, &quantity, "  ", &currency, side_max, qty_max, sym_max)?;
+  for order in orders {
+    let amount = &order.amount;
+    order_print(&order, amount, "", &currency, side_max, qty_max, sym_max)?;
+    for leg in &order.legs {
+      order_print(leg, amount, "  ", &currency, side_max, qty_max, sym_max)?;
     }
   }
   Ok(())
@@ -1391,11 +4343,35 @@ async fn order_list(client: Client, closed: bool) -> Result<()> {
 
 
 /// The handler for the 'position' command.
-async fn position(client: Client, position: Position) -> Result<()> {
+async fn position(
+  client: Client,
+  position: Position,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
   match position {
-    Position::Close { symbol } => position_close(client, symbol).await,
-    Position::Get { symbol } => position_get(client, symbol).await,
-    Position::List => position_list(client).await,
+    Position::Close {
+      symbol,
+      quantity,
+      percentage,
+    } => {
+      let request = endpoint::ClosePositionReq {
+        symbol: symbol.0,
+        quantity,
+        percentage,
+      };
+      position_close(client, request, confirm, dry_run, output).await
+    },
+    Position::CloseAll {
+      cancel_orders,
+      exclude,
+    } => {
+      let exclude = exclude.into_iter().flatten().collect();
+      position_close_all(client, cancel_orders, exclude, confirm, dry_run, output).await
+    },
+    Position::Get { symbol } => position_get(client, symbol, output).await,
+    Position::List => position_list(client, output).await,
   }
 }
 
@@ -1407,7 +4383,7 @@ fn format_position_side(side: position::Side) -> &'static str {
 }
 
 /// Retrieve and print a position for a given symbol.
-async fn position_get(client: Client, symbol: Symbol) -> Result<()> {
+async fn position_get(client: Client, symbol: Symbol, output: Output) -> Result<()> {
   let currency = client.issue::<account::Get>(&());
   let position = client.issue::<position::Get>(&symbol.0);
 
@@ -1418,6 +4394,10 @@ async fn position_get(client: Client, symbol: Symbol) -> Result<()> {
   let position =
     position.with_context(|| format!("failed to retrieve position for {}", symbol.0))?;
 
+  if output == Output::Json {
+    return print_json(&position)
+  }
+
   println!(
     r#"{sym}:
   asset id:               {id}
@@ -1452,15 +4432,73 @@ async fn position_get(client: Client, symbol: Symbol) -> Result<()> {
 
 
 /// Liquidate a position for a certain asset.
-async fn position_close(client: Client, symbol: Symbol) -> Result<()> {
+async fn position_close(
+  client: Client,
+  request: endpoint::ClosePositionReq,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
+  let symbol = &request.symbol;
+
+  if let Some(quantity) = &request.quantity {
+    ensure!(quantity.is_positive(), "quantity must be positive");
+
+    if !quantity.fract().is_zero() {
+      let asset = client
+        .issue::<asset::Get>(symbol)
+        .await
+        .with_context(|| format!("failed to retrieve asset information for {symbol}"))?;
+      ensure!(
+        asset.fractionable,
+        "{symbol} is not fractionable; quantity {quantity} has to be a whole number"
+      );
+    }
+  }
+
+  if let Some(percentage) = &request.percentage {
+    ensure!(
+      percentage.is_positive() && *percentage <= Num::from(100),
+      "percentage must be greater than 0 and at most 100"
+    );
+  }
+
+  if dry_run || confirm != Confirm::Never {
+    let position = client
+      .issue::<position::Get>(symbol)
+      .await
+      .with_context(|| format!("failed to retrieve position for {symbol}"))?;
+
+    if dry_run {
+      return DryRun::ClosePosition {
+        symbol: &position.symbol,
+        quantity: request.quantity.as_ref(),
+        percentage: request.percentage.as_ref(),
+      }
+      .print(output)
+    }
+
+    let quantity = match (&request.quantity, &request.percentage) {
+      (Some(quantity), _) => quantity.clone(),
+      (None, Some(percentage)) => &position.quantity * percentage / 100,
+      (None, None) => position.quantity.clone(),
+    };
+    let summary = format_close_summary(&position, &quantity);
+    let () = confirm.ask(&format!("close position in {symbol}"), &summary)?;
+  }
+
   let currency = client.issue::<account::Get>(&());
-  let order = client.issue::<position::Delete>(&symbol.0);
+  let order = client.issue::<endpoint::ClosePosition>(&request);
 
   let (currency, order) = join!(currency, order);
   let currency = currency
     .with_context(|| "failed to retrieve account information")?
     .currency;
-  let order = order.with_context(|| format!("failed to liquidate position for {}", symbol.0))?;
+  let order = order.with_context(|| format!("failed to liquidate position for {symbol}"))?;
+
+  if output == Output::Json {
+    return print_json(&order)
+  }
 
   println!(
     r#"{sym}:
@@ -1487,6 +4525,536 @@ async fn position_close(client: Client, symbol: Symbol) -> Result<()> {
 }
 
 
+/// Format the summary of (part of) a position that is about to be
+/// closed.
+fn format_close_summary(position: &position::Position, quantity: &Num) -> String {
+  // Closing a position means trading in the opposite direction.
+  let side = match position.side {
+    position::Side::Long => order::Side::Sell,
+    position::Side::Short => order::Side::Buy,
+  };
+  let notional = position
+    .market_value
+    .as_ref()
+    .filter(|_| !position.quantity.is_zero())
+    .map(|value| value * quantity / &position.quantity);
+
+  format_order_summary(
+    &position.symbol,
+    side,
+    Some(quantity),
+    notional.as_ref(),
+    order::Type::Market,
+    order::TimeInForce::Day,
+  )
+}
+
+
+/// The maximum time to wait for canceled orders to actually be
+/// canceled.
+const CANCEL_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+/// The interval at which to poll orders while waiting for them to be
+/// canceled.
+const CANCEL_POLL_INTERVAL: StdDuration = StdDuration::from_millis(500);
+
+
+/// Cancel an order and wait for the cancellation to take effect.
+async fn cancel_order_and_wait(client: &Client, id: order::Id) -> Result<()> {
+  let () = client
+    .issue::<order::Delete>(&id)
+    .await
+    .with_context(|| format!("failed to cancel order {}", id.as_hyphenated()))?;
+
+  let deadline = Instant::now() + CANCEL_TIMEOUT;
+  loop {
+    let order = poll_order(client, id).await?;
+    if is_terminal(order.status) {
+      break Ok(())
+    }
+
+    ensure!(
+      Instant::now() < deadline,
+      "order {} was not canceled in time",
+      id.as_hyphenated()
+    );
+    let () = sleep(CANCEL_POLL_INTERVAL).await;
+  }
+}
+
+
+/// The outcome of closing the position in a single symbol as part of
+/// closing all positions.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum CloseResult {
+  /// The position was closed by the order with the given ID.
+  Closed { symbol: String, id: order::Id },
+  /// Open orders were canceled, but there was no position to close.
+  Canceled { symbol: String, orders: usize },
+  /// Canceling orders or closing the position failed.
+  Failed { symbol: String, error: String },
+}
+
+impl CloseResult {
+  /// Retrieve the symbol the result is about.
+  fn symbol(&self) -> &str {
+    match self {
+      Self::Closed { symbol, .. } | Self::Canceled { symbol, .. } | Self::Failed { symbol, .. } => {
+        symbol
+      },
+    }
+  }
+}
+
+
+/// Cancel the given orders in a symbol and then close the position in
+/// it, if any, reporting the ID of the closing order.
+async fn close_symbol(
+  client: &Client,
+  symbol: &str,
+  position: bool,
+  orders: &[order::Order],
+) -> Result<Option<order::Id>> {
+  for order in orders {
+    let () = cancel_order_and_wait(client, order.id).await?;
+  }
+
+  if !position {
+    return Ok(None)
+  }
+
+  let request = endpoint::ClosePositionReq {
+    symbol: asset::Symbol::Sym(symbol.to_string()),
+    quantity: None,
+    percentage: None,
+  };
+  let order = client
+    .issue::<endpoint::ClosePosition>(&request)
+    .await
+    .with_context(|| "failed to liquidate position")?;
+  Ok(Some(order.id))
+}
+
+
+/// Liquidate all open positions, except for those in excluded symbols.
+async fn position_close_all(
+  client: Client,
+  cancel_orders: bool,
+  exclude: HashSet<String>,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
+  let positions = client.issue::<positions::List>(&());
+  let orders = async {
+    if cancel_orders {
+      let request = endpoint::ListOrdersReq {
+        status: orders::Status::Open,
+        // Legs are canceled along with their parents.
+        nested: false,
+        ..Default::default()
+      };
+      list_orders(&client, request, None).await
+    } else {
+      Ok(Vec::new())
+    }
+  };
+
+  let (positions, orders) = join!(positions, orders);
+  let positions = positions
+    .with_context(|| "failed to list positions")?
+    .into_iter()
+    .filter(|position| !exclude.contains(&position.symbol))
+    .collect::<Vec<_>>();
+  let orders = orders?
+    .into_iter()
+    .filter(|order| !exclude.contains(&order.symbol))
+    .collect::<Vec<_>>();
+
+  if dry_run {
+    return DryRun::ClosePositions {
+      symbols: positions
+        .iter()
+        .map(|position| position.symbol.as_str())
+        .collect(),
+      orders: orders.iter().collect(),
+    }
+    .print(output)
+  }
+
+  if positions.is_empty() && orders.is_empty() {
+    return Ok(())
+  }
+
+  if confirm != Confirm::Never {
+    let order_summaries = iter(&orders)
+      .map(|order| format_cancel_summary(&client, order))
+      .buffered(MAX_CONCURRENCY)
+      .collect::<Vec<_>>()
+      .await;
+    let summaries = order_summaries
+      .into_iter()
+      .chain(
+        positions
+          .iter()
+          .map(|position| format_close_summary(position, &position.quantity)),
+      )
+      .collect::<Vec<_>>();
+    let summary = summaries.join("\n\n");
+    let action = format!(
+      "cancel {} order(s) and close {} position(s)",
+      orders.len(),
+      positions.len()
+    );
+    let () = confirm.ask(&action, &summary)?;
+  }
+
+  // Group everything by symbol, so that we can make sure to only close
+  // a position once all orders in its symbol are canceled (which
+  // otherwise may hold the shares to sell).
+  let mut symbols = BTreeMap::<String, (bool, Vec<order::Order>)>::new();
+  for position in positions {
+    symbols.entry(position.symbol).or_default().0 = true;
+  }
+  for order in orders {
+    let () = symbols
+      .entry(order.symbol.clone())
+      .or_default()
+      .1
+      .push(order);
+  }
+
+  let mut results = iter(symbols)
+    .map(|(symbol, (position, orders))| {
+      let client = &client;
+      async move {
+        let result = close_symbol(client, &symbol, position, &orders).await;
+        match result {
+          Ok(Some(id)) => CloseResult::Closed { symbol, id },
+          Ok(None) => CloseResult::Canceled {
+            symbol,
+            orders: orders.len(),
+          },
+          Err(error) => CloseResult::Failed {
+            symbol,
+            error: format!("{error:#}"),
+          },
+        }
+      }
+    })
+    .buffer_unordered(MAX_CONCURRENCY)
+    .collect::<Vec<_>>()
+    .await;
+  let () = results.sort_by(|a, b| a.symbol().cmp(b.symbol()));
+
+  if output == Output::Json {
+    let () = print_json(&results)?;
+  } else {
+    let symbol_max = max_width(&results, |result| result.symbol().len());
+    for result in &results {
+      let symbol = result.symbol();
+      match result {
+        CloseResult::Closed { id, .. } => {
+          println!("{symbol:<symbol_max$} {}", id.as_hyphenated())
+        },
+        CloseResult::Canceled { orders, .. } => {
+          println!("{symbol:<symbol_max$} canceled {orders} order(s)")
+        },
+        CloseResult::Failed { error, .. } => println!("{symbol:<symbol_max$} error: {error}"),
+      }
+    }
+  }
+
+  let failed = results
+    .iter()
+    .filter(|result| matches!(result, CloseResult::Failed { .. }))
+    .count();
+  ensure!(
+    failed == 0,
+    "{failed} of {} symbol(s) could not be handled",
+    results.len()
+  );
+  Ok(())
+}
+
+
+async fn portfolio(
+  client: Client,
+  portfolio: Portfolio,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
+  match portfolio {
+    Portfolio::Rebalance(rebalance) => {
+      portfolio_rebalance(client, rebalance, confirm, dry_run, output).await
+    },
+  }
+}
+
+
+/// Gather the information about the holding in a symbol required for
+/// rebalancing.
+async fn holding(
+  client: &Client,
+  symbol: &str,
+  position: Option<&position::Position>,
+  rounding: Rounding,
+) -> Result<Holding> {
+  if let Some(position) = position {
+    ensure!(
+      position.side == position::Side::Long,
+      "rebalancing short position in {symbol} is not supported"
+    );
+  }
+
+  let quantity = position
+    .map(|position| position.quantity.clone())
+    .unwrap_or_default();
+  let price = match position.and_then(|position| position.current_price.clone()) {
+    Some(price) => price,
+    None => estimate_price(client, symbol, order::Side::Buy).await?,
+  };
+  // Fractionability only matters if we are asked to trade fractional
+  // quantities.
+  let fractionable = if rounding == Rounding::Fractional {
+    client
+      .issue::<asset::Get>(&asset::Symbol::Sym(symbol.to_string()))
+      .await
+      .with_context(|| format!("failed to retrieve asset information for {symbol}"))?
+      .fractionable
+  } else {
+    false
+  };
+
+  let holding = Holding {
+    quantity,
+    price,
+    fractionable,
+  };
+  Ok(holding)
+}
+
+
+/// Submit orders for the given adjustments, recording the IDs of the
+/// submitted orders.
+async fn submit_adjustments(
+  client: &Client,
+  plan: &mut [Adjustment],
+  requests: Vec<(usize, order::CreateReq)>,
+) -> Result<Vec<order::Order>> {
+  let results = iter(requests)
+    .map(|(index, request)| async move {
+      let result = client
+        .issue::<order::Create>(&request)
+        .await
+        .with_context(|| format!("failed to submit order for {}", request.symbol));
+      (index, result)
+    })
+    .buffer_unordered(MAX_CONCURRENCY)
+    .collect::<Vec<_>>()
+    .await;
+
+  let mut orders = Vec::with_capacity(results.len());
+  let mut error = None;
+  for (index, result) in results {
+    match result {
+      Ok(order) => {
+        plan[index].order_id = Some(order.id);
+        let () = orders.push(order);
+      },
+      Err(err) => error = error.or(Some(err)),
+    }
+  }
+
+  match error {
+    None => Ok(orders),
+    Some(err) => Err(err),
+  }
+}
+
+
+/// Execute a rebalancing plan, selling before buying so that cash is
+/// available.
+async fn execute_rebalance(
+  client: &Client,
+  plan: &mut [Adjustment],
+  requests: Vec<(usize, order::CreateReq)>,
+  timeout: StdDuration,
+) -> Result<()> {
+  let (sells, buys) = requests
+    .into_iter()
+    .partition::<Vec<_>, _>(|(_, request)| request.side == order::Side::Sell);
+
+  if !sells.is_empty() {
+    // Subscribe before submitting, so that we can't miss fills.
+    let mut watcher = OrderWatcher::new(client).await;
+    let orders = submit_adjustments(client, plan, sells).await?;
+
+    let deadline = Instant::now() + timeout;
+    let settled = |order: &order::Order| is_terminal(order.status);
+    let mut open = Vec::new();
+    for order in orders {
+      let order = watcher
+        .wait(client, order, Some(deadline), settled, |_| ())
+        .await?;
+      if !is_terminal(order.status) {
+        let () = open.push(format!("{} ({})", order.id.as_hyphenated(), order.symbol));
+        continue
+      }
+
+      ensure!(
+        order.status == order::Status::Filled,
+        "sell order {} for {} was not filled ({}); not submitting buy orders",
+        order.id.as_hyphenated(),
+        order.symbol,
+        format_order_status(order.status),
+      );
+    }
+
+    ensure!(
+      open.is_empty(),
+      "sell order(s) not filled after {}s: {}; not submitting buy orders",
+      timeout.as_secs(),
+      open.join(", "),
+    );
+  }
+
+  let _orders = submit_adjustments(client, plan, buys).await?;
+  Ok(())
+}
+
+
+/// Print a rebalancing plan.
+fn print_plan(plan: &[Adjustment], currency: &str) {
+  let symbol_max = max_width(plan, |adjustment| adjustment.symbol.len());
+  for adjustment in plan {
+    let trade = match adjustment.side {
+      Some(side) => format!(
+        "{side} {quantity} (~{notional})",
+        side = format_order_side(side),
+        quantity = adjustment.quantity,
+        notional = format_price(&adjustment.notional, currency),
+      ),
+      None => "-".to_string(),
+    };
+    let order_id = adjustment
+      .order_id
+      .map(|id| format!(" {}", id.as_hyphenated()))
+      .unwrap_or_default();
+
+    println!(
+      "{symbol:<symbol_max$} {current:>7.2}% -> {target:>6.2}%  {trade}{order_id}",
+      symbol = adjustment.symbol,
+      current = adjustment.current_weight,
+      target = adjustment.target_weight,
+    );
+  }
+}
+
+
+/// Compute the trades necessary to rebalance the portfolio towards
+/// target weights and optionally execute them.
+async fn portfolio_rebalance(
+  client: Client,
+  rebalance: Rebalance,
+  confirm: Confirm,
+  dry_run: bool,
+  output: Output,
+) -> Result<()> {
+  let Rebalance {
+    targets,
+    tolerance,
+    rounding,
+    execute,
+    timeout,
+  } = rebalance;
+
+  ensure!(!tolerance.is_negative(), "tolerance must not be negative");
+  let targets = Targets::load(&targets)?;
+
+  let account = client.issue::<account::Get>(&());
+  let positions = client.issue::<positions::List>(&());
+
+  let (account, positions) = join!(account, positions);
+  let account = account.with_context(|| "failed to retrieve account information")?;
+  let positions = positions.with_context(|| "failed to list positions")?;
+
+  let holdings = targets
+    .0
+    .keys()
+    .map(|symbol| {
+      let position = positions.iter().find(|position| position.symbol == *symbol);
+      holding(&client, symbol, position, rounding).map_ok(|holding| (symbol.clone(), holding))
+    })
+    .collect::<FuturesOrdered<_>>()
+    .try_collect::<BTreeMap<_, _>>()
+    .await?;
+
+  let mut plan = rebalance::plan(&account.equity, &targets, &holdings, &tolerance, rounding)?;
+
+  let result = if execute {
+    let requests = plan
+      .iter()
+      .enumerate()
+      .filter_map(|(index, adjustment)| {
+        adjustment.side.map(|side| {
+          let request = order::CreateReqInit {
+            type_: order::Type::Market,
+            time_in_force: order::TimeInForce::Day,
+            ..Default::default()
+          }
+          .init(
+            &adjustment.symbol,
+            side,
+            order::Amount::quantity(adjustment.quantity.clone()),
+          );
+          (index, request)
+        })
+      })
+      .collect::<Vec<_>>();
+
+    if dry_run {
+      let requests = requests.iter().map(|(_, request)| request).collect();
+      return DryRun::SubmitOrders { requests }.print(output)
+    }
+
+    if confirm != Confirm::Never && !requests.is_empty() {
+      let summary = requests
+        .iter()
+        .map(|(index, request)| {
+          let adjustment = &plan[*index];
+          format_order_summary(
+            &adjustment.symbol,
+            request.side,
+            Some(&adjustment.quantity),
+            Some(&adjustment.notional),
+            request.type_,
+            request.time_in_force,
+          )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+      let action = format!("submit {} order(s)", requests.len());
+      let () = confirm.ask(&action, &summary)?;
+    }
+
+    execute_rebalance(&client, &mut plan, requests, timeout).await
+  } else {
+    Ok(())
+  };
+
+  // We print the plan even if execution failed half way, so that the
+  // orders that did go out are known.
+  if output == Output::Json {
+    let () = print_json(&plan)?;
+  } else {
+    let () = print_plan(&plan, &account.currency);
+  }
+  result
+}
+
+
 /// Format a price value.
 fn format_price(price: &Num, currency: &str) -> Str {
   format!("{price:.2} {currency}").into()
@@ -1734,25 +5302,170 @@ fn position_print(positions: &[position::Position], currency: &str) {
   );
 }
 
-/// List all currently open positions.
-async fn position_list(client: Client) -> Result<()> {
+/// List all currently open positions.
+async fn position_list(client: Client, output: Output) -> Result<()> {
+  let account = client.issue::<account::Get>(&());
+  let positions = client.issue::<positions::List>(&());
+
+  let (account, positions) = join!(account, positions);
+  let account = account.with_context(|| "failed to retrieve account information")?;
+  let mut positions = positions.with_context(|| "failed to list positions")?;
+  positions.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+  if output == Output::Json {
+    return print_json(&positions)
+  }
+
+  if !positions.is_empty() {
+    position_print(&positions, &account.currency);
+  }
+  Ok(())
+}
+
+
+/// Compute the change of the last trade price relative to the close
+/// of the previous trading day, in absolute terms and as a fraction.
+fn daily_change(snapshot: &endpoint::Snapshot) -> Option<(Num, Num)> {
+  let last = &snapshot.latest_trade.as_ref()?.price;
+  let close = &snapshot.prev_daily_bar.as_ref()?.close;
+  if close.is_zero() {
+    return None
+  }
+
+  let change = last - close;
+  let percent = &change / close;
+  Some((change, percent))
+}
+
+
+/// Print market data snapshots for the given symbols in a table.
+fn quote_print(
+  symbols: &[String],
+  snapshots: &BTreeMap<String, endpoint::Snapshot>,
+  currency: &str,
+) {
+  let rows = symbols
+    .iter()
+    .map(|symbol| {
+      let snapshot = snapshots.get(symbol);
+      let quote = snapshot.and_then(|snapshot| snapshot.latest_quote.as_ref());
+      let bid = quote
+        .map(|quote| {
+          format!(
+            "{} x {}",
+            format_price(&quote.bid_price, currency),
+            quote.bid_size
+          )
+        })
+        .unwrap_or_else(|| "N/A".to_string());
+      let ask = quote
+        .map(|quote| {
+          format!(
+            "{} x {}",
+            format_price(&quote.ask_price, currency),
+            quote.ask_size
+          )
+        })
+        .unwrap_or_else(|| "N/A".to_string());
+      let last = snapshot
+        .and_then(|snapshot| snapshot.latest_trade.as_ref())
+        .map(|trade| trade.price.clone());
+      let (change, change_pct) = snapshot.and_then(daily_change).unzip();
+      (symbol, bid, ask, last, change, change_pct)
+    })
+    .collect::<Vec<_>>();
+
+  let symbol = "Symbol";
+  let sym_max = max(max_width(&rows, |r| r.0.len()), symbol.len());
+  let bid = "Bid";
+  let bid_max = max(max_width(&rows, |r| r.1.len()), bid.len());
+  let ask = "Ask";
+  let ask_max = max(max_width(&rows, |r| r.2.len()), ask.len());
+  let last = "Last";
+  let last_max = max(
+    max_width(&rows, |r| format_option_price(&r.3, currency).len()),
+    last.len(),
+  );
+  let change_max = max_width(&rows, |r| format_option_price(&r.4, currency).len());
+  let change_pct_max = max_width(&rows, |r| format_option_percent(&r.5).len());
+  let change = "Change";
+  let change_col = max(change_max + 2 + change_pct_max + 1, change.len());
+
+  println!(
+    "{symbol:<sym_max$} | {bid:^bid_max$} | {ask:^ask_max$} | {last:^last_max$} | \
+     {change:^change_col$}"
+  );
+
+  for (symbol, bid, ask, last, change, change_pct) in rows {
+    println!(
+      "{symbol:<sym_max$} | {bid:>bid_max$} | {ask:>ask_max$} | {last:>last_max$} | \
+       {change:>change_max$} ({change_pct:>change_pct_max$})",
+      last = format_option_price(&last, currency),
+      change = format_option_gain(&change, currency),
+      change_pct = format_option_percent_gain(&change_pct),
+    );
+  }
+}
+
+
+/// Retrieve the latest quote, trade, and daily change for a set of
+/// symbols.
+async fn quote(client: Client, quote: Quote, output: Output) -> Result<()> {
+  let Quote { symbols, source } = quote;
+  let symbols = symbols
+    .into_iter()
+    .map(|symbol| symbol.to_uppercase())
+    .collect::<Vec<_>>();
+
+  let request = endpoint::SnapshotsReq {
+    symbols: symbols.clone(),
+    feed: source.to_feed(),
+  };
   let account = client.issue::<account::Get>(&());
-  let positions = client.issue::<positions::List>(&());
+  let snapshots = client.issue::<endpoint::Snapshots>(&request);
 
-  let (account, positions) = join!(account, positions);
+  let (account, snapshots) = join!(account, snapshots);
   let account = account.with_context(|| "failed to retrieve account information")?;
-  let mut positions = positions.with_context(|| "failed to list positions")?;
+  let snapshots = snapshots.with_context(|| "failed to retrieve market data snapshots")?;
 
-  if !positions.is_empty() {
-    positions.sort_by(|a, b| a.symbol.cmp(&b.symbol));
-    position_print(&positions, &account.currency);
+  if output == Output::Json {
+    return print_json(&snapshots)
   }
+
+  quote_print(&symbols, &snapshots, &account.currency);
   Ok(())
 }
 
 
+/// The JSON representation of a watch list.
+#[derive(Debug, Serialize)]
+struct JsonWatchlist<'w> {
+  id: Uuid,
+  name: &'w str,
+  created_at: DateTime<Utc>,
+  updated_at: DateTime<Utc>,
+  symbols: Vec<&'w str>,
+}
+
+impl<'w> From<&'w watchlist::Watchlist> for JsonWatchlist<'w> {
+  fn from(watchlist: &'w watchlist::Watchlist) -> Self {
+    Self {
+      id: watchlist.id.0,
+      name: &watchlist.name,
+      created_at: watchlist.created_at,
+      updated_at: watchlist.updated_at,
+      symbols: watchlist
+        .assets
+        .iter()
+        .map(|asset| asset.symbol.as_str())
+        .collect(),
+    }
+  }
+}
+
+
 /// Create a new watch list with the given name.
-async fn watchlist_create(client: Client, create: CreateWatchlist) -> Result<()> {
+async fn watchlist_create(client: Client, create: CreateWatchlist, output: Output) -> Result<()> {
   let CreateWatchlist { name, symbols } = create;
   let request = watchlist::CreateReqInit {
     symbols,
@@ -1765,13 +5478,17 @@ async fn watchlist_create(client: Client, create: CreateWatchlist) -> Result<()>
     .await
     .context("failed to create watch list")?;
 
+  if output == Output::Json {
+    return print_json(&JsonWatchlist::from(&watchlist))
+  }
+
   println!("{}", watchlist.id.as_hyphenated());
   Ok(())
 }
 
 
 /// List all watch lists.
-async fn watchlist_list(client: Client) -> Result<()> {
+async fn watchlist_list(client: Client, output: Output) -> Result<()> {
   let watchlists = client
     .issue::<watchlists::Get>(&())
     .await
@@ -1796,6 +5513,12 @@ async fn watchlist_list(client: Client) -> Result<()> {
     })
     .await?;
 
+  if output == Output::Json {
+    let () = lists.sort_by(|x, y| x.name.cmp(&y.name));
+    let lists = lists.iter().map(JsonWatchlist::from).collect::<Vec<_>>();
+    return print_json(&lists)
+  }
+
   let name_max = max_width(&lists, |l| l.name.len());
 
   for list in lists {
@@ -1811,12 +5534,16 @@ async fn watchlist_list(client: Client) -> Result<()> {
 
 
 /// Retrieve and print information about a watch list.
-async fn watchlist_get(client: Client, id: WatchlistId) -> Result<()> {
+async fn watchlist_get(client: Client, id: WatchlistId, output: Output) -> Result<()> {
   let watchlist = client
     .issue::<watchlist::Get>(&id.0)
     .await
     .with_context(|| format!("failed to retrieve watch list `{}`", id.0.as_hyphenated()))?;
 
+  if output == Output::Json {
+    return print_json(&JsonWatchlist::from(&watchlist))
+  }
+
   println!(
     r#"{name}:
   id:              {id}
@@ -1906,11 +5633,11 @@ async fn watchlist_delete(client: Client, id: WatchlistId) -> Result<()> {
 
 
 /// The handler for the `watchlist` command.
-async fn watchlist(client: Client, watchlist: Watchlist) -> Result<()> {
+async fn watchlist(client: Client, watchlist: Watchlist, output: Output) -> Result<()> {
   match watchlist {
-    Watchlist::Create(create) => watchlist_create(client, create).await,
-    Watchlist::List => watchlist_list(client).await,
-    Watchlist::Get { id } => watchlist_get(client, id).await,
+    Watchlist::Create(create) => watchlist_create(client, create, output).await,
+    Watchlist::List => watchlist_list(client, output).await,
+    Watchlist::Get { id } => watchlist_get(client, id, output).await,
     Watchlist::Update(update) => watchlist_update(client, update).await,
     Watchlist::Delete { id } => watchlist_delete(client, id).await,
   }
@@ -2025,7 +5752,10 @@ fn extension_dirs(exe: &Path) -> impl IntoIterator<Item = PathBuf> {
 }
 
 /// Run an extension.
-fn extension(args: Vec<OsString>) -> Result<()> {
+///
+/// If an `ApiInfo` object is provided, the extension is made aware of
+/// it through the `APCA_*` environment variables.
+fn extension(args: Vec<OsString>, api_info: Option<ApiInfo>) -> Result<()> {
   // Note that while `Command` would actually honor PATH by itself, we
   // do not want to rely on that behavior in order to have the ability
   // to provide better error messages if a non-executable extension is
@@ -2036,10 +5766,18 @@ fn extension(args: Vec<OsString>) -> Result<()> {
   let dirs = extension_dirs(&exe);
   let ext_path = resolve_extension(dirs, &ext_name)?;
 
-  let err = Process::new(&ext_path)
-    .args(args)
-    .env("APCACLI", exe)
-    .exec();
+  let mut process = Process::new(&ext_path);
+  let _ = process.args(args).env("APCACLI", exe);
+
+  if let Some(api_info) = api_info {
+    let _ = process
+      .env("APCA_API_BASE_URL", api_info.api_base_url.as_str())
+      .env("APCA_API_STREAM_URL", api_info.api_stream_url.as_str())
+      .env("APCA_API_KEY_ID", api_info.key_id)
+      .env("APCA_API_SECRET_KEY", api_info.secret);
+  }
+
+  let err = process.exec();
   Err(err).with_context(|| format!("failed to execute extension {}", ext_path.display()))
 }
 
@@ -2143,21 +5881,72 @@ async fn run() -> Result<()> {
   let () = init_logging(args.verbosity).context("failed to initialize logging infrastructure")?;
 
   if let Command::Extension(command) = args.command {
-    self::extension(command)
+    // Extensions retrieve their configuration from the environment
+    // themselves, unless a profile was selected explicitly.
+    let api_info = args
+      .profile
+      .as_deref()
+      .map(|profile| config::access(Some(profile)).map(|access| access.api_info))
+      .transpose()?;
+    self::extension(command, api_info)
+  } else if let Command::Updates(Updates::Replay {
+    file,
+    speed,
+    compact,
+    currency,
+  }) = args.command
+  {
+    // Replaying does not talk to Alpaca and so does not require any
+    // credentials.
+    replay_updates(&file, speed, compact, &currency, args.output).await
+  } else if let Command::Alert(alert @ (Alert::Add(..) | Alert::List | Alert::Remove { .. })) =
+    args.command
+  {
+    // Managing alerts only touches the alerts file and so does not
+    // require any credentials.
+    self::alert(alert, args.output)
   } else {
-    let api_info =
-      ApiInfo::from_env().with_context(|| "failed to retrieve Alpaca environment information")?;
+    let config::Access { api_info, live } = config::access(args.profile.as_deref())?;
+    let dry_run = args.dry_run;
+    // Nothing is modified in dry-run mode and so there is nothing to
+    // confirm either.
+    let confirm = if live && !args.yes && !dry_run {
+      Confirm::Interactive { live }
+    } else {
+      Confirm::Never
+    };
     let client = Client::new(api_info);
 
+    let output = args.output;
+
     match args.command {
-      Command::Account(account) => self::account(client, account).await,
-      Command::Asset(asset) => self::asset(client, asset).await,
-      Command::Bars(bars) => self::bars(client, bars).await,
-      Command::Market => self::market(client).await,
-      Command::Order(order) => self::order(client, order).await,
-      Command::Position(position) => self::position(client, position).await,
-      Command::Updates(updates) => self::updates(client, updates).await,
-      Command::Watchlist(watchlist) => self::watchlist(client, watchlist).await,
+      Command::Account(account) => self::account(client, account, output).await,
+      Command::Alert(Alert::Watch {
+        source,
+        bars,
+        reconnect,
+      }) => alert_watch(client, source, bars, reconnect, output).await,
+      Command::Alert(..) => unreachable!(),
+      Command::Asset(asset) => self::asset(client, asset, output).await,
+      Command::Bars(bars) => self::bars(client, bars, output).await,
+      Command::Market => self::market(client, output).await,
+      Command::Order(order) => self::order(client, order, confirm, dry_run, output).await,
+      Command::Portfolio(portfolio) => {
+        self::portfolio(client, portfolio, confirm, dry_run, output).await
+      },
+      Command::Position(position) => {
+        // Closing all positions is drastic enough to always ask for
+        // confirmation, not just on live accounts.
+        let confirm = if matches!(position, Position::CloseAll { .. }) && !args.yes && !dry_run {
+          Confirm::Interactive { live }
+        } else {
+          confirm
+        };
+        self::position(client, position, confirm, dry_run, output).await
+      },
+      Command::Quote(quote) => self::quote(client, quote, output).await,
+      Command::Updates(updates) => self::updates(client, updates, output).await,
+      Command::Watchlist(watchlist) => self::watchlist(client, watchlist, output).await,
       Command::Extension(..) => unreachable!(),
     }
   }
@@ -2172,12 +5961,15 @@ fn main() -> ExitCode {
   let exit_code = rt
     .block_on(run())
     .map(|_| ExitCode::SUCCESS)
-    .map_err(|e| {
+    .unwrap_or_else(|e| {
       eprint!("{e}");
       e.chain().skip(1).for_each(|cause| eprint!(": {cause}"));
       eprintln!();
-    })
-    .unwrap_or(ExitCode::FAILURE);
+
+      e.downcast_ref::<WaitError>()
+        .map(|err| err.exit_code())
+        .unwrap_or(ExitCode::FAILURE)
+    });
 
   exit_code
 }
@@ -2187,7 +5979,10 @@ fn main() -> ExitCode {
 mod tests {
   use super::*;
 
-  use std::fs::File;
+  use crate::args::OrderType;
+
+  use serde_json::json;
+  use serde_json::to_value as to_json_value;
 
   use tempfile::tempdir;
 
@@ -2206,6 +6001,964 @@ mod tests {
     assert_eq!(format_approximate_quantity(&Num::new(4, 100)), "0.04");
   }
 
+  /// Check that recorded stream messages can be loaded again.
+  #[test]
+  fn record_and_load_updates() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("updates.ndjson");
+
+    // A quote as sent by Alpaca, with an additional field not known
+    // to `apca` and without the type tag, which `apca` strips.
+    let raw = r#"{
+      "S": "SPY",
+      "bx": "V",
+      "bp": 472.6,
+      "bs": 3,
+      "ax": "V",
+      "ap": 472.7,
+      "as": 12,
+      "t": "2024-01-02T20:59:59Z"
+    }"#;
+    let quote = stream::Quote {
+      symbol: "SPY".to_string(),
+      bid_price: Num::new(4726, 10),
+      bid_size: Num::from(3),
+      ask_price: Num::new(4727, 10),
+      ask_size: Num::from(12),
+      timestamp: DateTime::parse_from_rfc3339("2024-01-02T20:59:59Z")
+        .unwrap()
+        .into(),
+    };
+    let update = updates::OrderUpdate {
+      event: updates::OrderStatus::Filled,
+      order: sample_order(),
+    };
+
+    let mut recorder = Recorder::create(&path).unwrap();
+    let data = stream::Data::Quote(from_json::<JsonValue>(raw).unwrap());
+    let message = RecordedMessage::from_data(data).unwrap();
+    let () = recorder.record(message).unwrap();
+    let () = recorder
+      .record(RecordedMessage::TradeUpdate(update.clone()))
+      .unwrap();
+    let () = recorder
+      .record(RecordedMessage::Unparsable {
+        error: "expected value".to_string(),
+      })
+      .unwrap();
+    drop(recorder);
+
+    let content = read_to_string(&path).unwrap();
+    let line = content.lines().next().unwrap();
+    let json = from_json::<JsonValue>(line).unwrap();
+    assert_eq!(json["message"]["T"], "q");
+    assert_eq!(json["message"]["bp"], 472.6);
+    assert_eq!(json["message"]["bx"], "V");
+
+    let records = load_recording(&path)
+      .unwrap()
+      .collect::<Result<Vec<_>>>()
+      .unwrap();
+    assert_eq!(records.len(), 3);
+    assert!(records[0].time <= records[1].time);
+    match records[0].message.to_data() {
+      Some(Ok(stream::Data::Quote(parsed))) => assert_eq!(parsed, quote),
+      _ => panic!("unexpected message: {:?}", records[0].message),
+    }
+    assert_eq!(records[1].message, RecordedMessage::TradeUpdate(update));
+    assert!(records[1].message.to_data().is_none());
+    assert_eq!(
+      records[2].message,
+      RecordedMessage::Unparsable {
+        error: "expected value".to_string()
+      }
+    );
+
+    let () = std::fs::write(&path, "\n{}\n").unwrap();
+    let err = load_recording(&path)
+      .unwrap()
+      .collect::<Result<Vec<_>>>()
+      .unwrap_err();
+    assert!(
+      err.to_string().starts_with("failed to parse line 2 of"),
+      "{err}"
+    );
+  }
+
+  /// Check that reconnection delays increase exponentially, up to a
+  /// maximum.
+  #[test]
+  fn reconnect_backoff() {
+    let mut backoff = Backoff::default();
+    let delays = (0..8)
+      .map(|_| backoff.next_delay().as_secs())
+      .collect::<Vec<_>>();
+    assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+
+    let () = backoff.reset();
+    assert_eq!(backoff.next_delay(), RECONNECT_DELAY_MIN);
+  }
+
+  /// Check that we report only orders updated since a given time as
+  /// missed trade updates, in the order in which they were updated.
+  #[test]
+  fn missed_trade_update_filtering() {
+    let time = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+    // An old resting order filled in the gap.
+    let mut filled = sample_order();
+    filled.id = order::Id(Uuid::new_v4());
+    filled.submitted_at = Some(time("2018-09-01T10:00:00Z"));
+    filled.updated_at = Some(time("2018-10-05T10:02:00Z"));
+    filled.status = order::Status::Filled;
+    // A new order submitted in the gap.
+    let mut new = sample_order();
+    new.id = order::Id(Uuid::new_v4());
+    new.updated_at = Some(time("2018-10-05T10:01:00Z"));
+    new.status = order::Status::New;
+    // An order last updated before the gap.
+    let mut canceled = sample_order();
+    canceled.updated_at = Some(time("2018-10-05T09:59:00Z"));
+    canceled.status = order::Status::Canceled;
+    // An order in a state we can't represent as a trade update.
+    let mut unknown = sample_order();
+    unknown.updated_at = Some(time("2018-10-05T10:03:00Z"));
+    unknown.status = order::Status::Unknown;
+
+    let orders = vec![unknown, filled.clone(), canceled, new.clone()];
+    let updates = order_updates_since(orders, time("2018-10-05T10:00:00Z"));
+    let updates = updates
+      .into_iter()
+      .map(|update| (update.order.id, update.event))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      updates,
+      vec![
+        (new.id, updates::OrderStatus::New),
+        (filled.id, updates::OrderStatus::Filled),
+      ]
+    );
+  }
+
+  /// Check that realtime market data updates are represented as
+  /// expected.
+  #[test]
+  fn realtime_data_formatting() {
+    let quote = stream::Quote {
+      symbol: "SPY".to_string(),
+      bid_price: Num::new(4726, 10),
+      bid_size: Num::from(3),
+      ask_price: Num::new(4727, 10),
+      ask_size: Num::from(12),
+      timestamp: DateTime::parse_from_rfc3339("2024-01-02T20:59:59Z")
+        .unwrap()
+        .into(),
+    };
+    let json = to_json_value(JsonData::Quote(&quote)).unwrap();
+    let expected = json!({
+      "type": "quote",
+      "S": "SPY",
+      "bp": "472.6",
+      "bs": "3",
+      "ap": "472.7",
+      "as": "12",
+      "t": "2024-01-02T20:59:59Z",
+    });
+    assert_eq!(json, expected);
+
+    let line = format_data_compact(&stream::Data::Quote(quote)).unwrap();
+    assert!(
+      line.ends_with(" SPY quote bid 472.6 x 3 ask 472.7 x 12"),
+      "{line}"
+    );
+
+    let trade = stream::Trade {
+      symbol: "SPY".to_string(),
+      trade_id: 42,
+      trade_price: Num::new(47265, 100),
+      trade_size: Num::from(100),
+      timestamp: DateTime::parse_from_rfc3339("2024-01-02T20:59:59Z")
+        .unwrap()
+        .into(),
+    };
+    let line = format_data_compact(&stream::Data::Trade(trade)).unwrap();
+    assert!(line.ends_with(" SPY trade 100 @ 472.65"), "{line}");
+  }
+
+  /// Check that we compute the daily change of a symbol correctly.
+  #[test]
+  fn daily_change_computation() {
+    let snapshot = serde_json::from_value::<endpoint::Snapshot>(json!({
+      "latestTrade": {"t": "2024-01-02T20:59:59Z", "p": 99, "s": 10},
+      "prevDailyBar": {
+        "t": "2023-12-29T05:00:00Z",
+        "o": 101, "h": 102, "l": 99, "c": 100, "v": 1000
+      },
+    }))
+    .unwrap();
+    let (change, percent) = daily_change(&snapshot).unwrap();
+    assert_eq!(change, Num::from(-1));
+    assert_eq!(percent, Num::new(-1, 100));
+
+    let snapshot = endpoint::Snapshot {
+      prev_daily_bar: None,
+      ..snapshot
+    };
+    assert_eq!(daily_change(&snapshot), None);
+  }
+
+  /// Check that we format order amounts in lists as expected.
+  #[test]
+  fn list_amount_formatting() {
+    let amount = order::Amount::quantity(5);
+    assert_eq!(format_list_amount(&amount, "USD"), "5");
+
+    let amount = order::Amount::notional(1000);
+    assert_eq!(format_list_amount(&amount, "USD"), "1000.00 USD");
+  }
+
+  /// Check that order summaries presented for confirmation are
+  /// formatted as expected.
+  #[test]
+  fn order_summary_formatting() {
+    let summary = format_order_summary(
+      "SPY",
+      order::Side::Buy,
+      Some(&Num::from(5)),
+      Some(&Num::new(200125, 100)),
+      order::Type::Limit,
+      order::TimeInForce::Day,
+    );
+    let expected = r#"  symbol:           SPY
+  side:             buy
+  quantity:         ~5
+  notional:         ~2001.25
+  type:             limit
+  good until:       today"#;
+    assert_eq!(summary, expected);
+
+    let summary = format_order_summary(
+      "AAPL",
+      order::Side::Sell,
+      None,
+      None,
+      order::Type::Market,
+      order::TimeInForce::UntilCanceled,
+    );
+    assert!(summary.contains("quantity:         N/A"), "{summary}");
+    assert!(summary.contains("notional:         N/A"), "{summary}");
+  }
+
+  /// Check that we determine order types correctly.
+  #[test]
+  fn order_type_determination() {
+    let price = Some(Num::from(42));
+    assert_eq!(
+      determine_order_type(&None, &None, false),
+      order::Type::Market
+    );
+    assert_eq!(
+      determine_order_type(&price, &None, false),
+      order::Type::Limit
+    );
+    assert_eq!(
+      determine_order_type(&None, &price, false),
+      order::Type::Stop
+    );
+    assert_eq!(
+      determine_order_type(&price, &price, false),
+      order::Type::StopLimit
+    );
+    assert_eq!(
+      determine_order_type(&None, &None, true),
+      order::Type::TrailingStop
+    );
+  }
+
+  /// Check that fractional and notional orders default to and are
+  /// restricted to being valid for the day.
+  #[test]
+  fn time_in_force_determination() {
+    assert_eq!(
+      determine_time_in_force(None, false).unwrap(),
+      order::TimeInForce::UntilCanceled
+    );
+    assert_eq!(
+      determine_time_in_force(Some(TimeInForce::MarketOpen), false).unwrap(),
+      order::TimeInForce::UntilMarketOpen
+    );
+    assert_eq!(
+      determine_time_in_force(None, true).unwrap(),
+      order::TimeInForce::Day
+    );
+    assert_eq!(
+      determine_time_in_force(Some(TimeInForce::Today), true).unwrap(),
+      order::TimeInForce::Day
+    );
+
+    let err = determine_time_in_force(Some(TimeInForce::Canceled), true).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "fractional and notional orders can only be valid for the day ('today')"
+    );
+  }
+
+  /// Check that we validate the prices of one-cancels-other orders
+  /// against the current market price.
+  #[test]
+  fn oco_price_validation() {
+    let price = Num::from(100);
+    let () =
+      validate_oco_prices(order::Side::Sell, &price, &Num::from(110), &Num::from(90)).unwrap();
+    let () =
+      validate_oco_prices(order::Side::Buy, &price, &Num::from(90), &Num::from(110)).unwrap();
+
+    let err =
+      validate_oco_prices(order::Side::Sell, &price, &Num::from(90), &Num::from(80)).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "take-profit price 90 must be above current bid price 100 for sell order"
+    );
+
+    let err =
+      validate_oco_prices(order::Side::Buy, &price, &Num::from(90), &Num::from(100)).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "stop-loss stop price 100 must be above current ask price 100 for buy order"
+    );
+  }
+
+  /// Create an order object for testing purposes.
+  fn sample_order() -> order::Order {
+    let json = r#"{
+      "id": "904837e3-3b76-47ec-b432-046db621571b",
+      "client_order_id": "904837e3-3b76-47ec-b432-046db621571b",
+      "created_at": "2018-10-05T05:48:59Z",
+      "updated_at": "2018-10-05T05:48:59Z",
+      "submitted_at": "2018-10-05T05:48:59Z",
+      "filled_at": null,
+      "expired_at": null,
+      "canceled_at": null,
+      "failed_at": null,
+      "asset_id": "904837e3-3b76-47ec-b432-046db621571b",
+      "symbol": "AAPL",
+      "asset_class": "us_equity",
+      "qty": "15",
+      "filled_qty": "0",
+      "type": "trailing_stop",
+      "order_class": "simple",
+      "side": "sell",
+      "time_in_force": "day",
+      "limit_price": null,
+      "stop_price": "95",
+      "trail_price": "5",
+      "trail_percent": null,
+      "status": "new",
+      "extended_hours": false,
+      "legs": null
+    }"#;
+    from_json::<order::Order>(json).unwrap()
+  }
+
+  /// Check that we map the terminal status of an order we waited for
+  /// to the documented exit codes.
+  #[test]
+  fn wait_exit_codes() {
+    let statuses = [
+      (order::Status::Filled, None),
+      (order::Status::Canceled, Some(3)),
+      (order::Status::Replaced, Some(3)),
+      (order::Status::Expired, Some(4)),
+      (order::Status::Rejected, Some(5)),
+      (order::Status::New, Some(6)),
+      (order::Status::PartiallyFilled, Some(6)),
+    ];
+
+    for (status, expected) in statuses {
+      let code = WaitError::check(status).err().map(WaitError::code);
+      assert_eq!(code, expected, "{status:?}");
+    }
+
+    // The error has to be recoverable for the exit code to be
+    // reported, even if context got attached along the way.
+    let err = Error::from(WaitError::Expired).context("failed to wait for order");
+    assert_eq!(err.downcast_ref::<WaitError>(), Some(&WaitError::Expired));
+  }
+
+  /// Check that we only allow changing the trail of trailing stop
+  /// orders.
+  #[test]
+  fn trailing_stop_changing() {
+    let order = sample_order();
+    let price = Num::from(100);
+
+    let trail = trailing_stop_change(&order, None, None, Some(Num::from(3)), None).unwrap();
+    assert_eq!(trail, Some(Num::from(3)));
+    let trail = trailing_stop_change(&order, None, None, None, None).unwrap();
+    assert_eq!(trail, None);
+
+    let err = trailing_stop_change(&order, None, None, None, Some(Num::from(3))).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "order trails by price; use --trail-price to change it"
+    );
+
+    let expected = "order 904837e3-3b76-47ec-b432-046db621571b is a trailing stop order; \
+                    only --trail-price/--trail-percent can be changed";
+    let err = trailing_stop_change(&order, None, Some(&price), None, None).unwrap_err();
+    assert_eq!(err.to_string(), expected);
+    let err = trailing_stop_change(&order, Some(&price), None, None, None).unwrap_err();
+    assert_eq!(err.to_string(), expected);
+  }
+
+  /// Check that only orders resting at a price target are considered
+  /// waiting.
+  #[test]
+  fn order_waiting_determination() {
+    let mut order = sample_order();
+    assert!(is_waiting(&order));
+
+    order.status = order::Status::Filled;
+    assert!(!is_waiting(&order));
+
+    order.status = order::Status::Accepted;
+    order.type_ = order::Type::Market;
+    assert!(!is_waiting(&order));
+  }
+
+  /// Check that we can recover the high water mark of trailing stop
+  /// orders.
+  #[test]
+  fn trailing_stop_high_water_mark() {
+    let mut order = sample_order();
+    assert_eq!(high_water_mark(&order), Some(Num::from(100)));
+    assert_eq!(format_trail(&order, "USD"), "5.00 USD");
+
+    order.side = order::Side::Buy;
+    order.stop_price = Some(Num::from(105));
+    assert_eq!(high_water_mark(&order), Some(Num::from(100)));
+
+    order.trail_price = None;
+    order.trail_percent = Some(Num::from(5));
+    assert_eq!(high_water_mark(&order), Some(Num::from(100)));
+    assert_eq!(format_trail(&order, "USD"), "5.00%");
+
+    order.side = order::Side::Sell;
+    order.stop_price = Some(Num::from(95));
+    assert_eq!(high_water_mark(&order), Some(Num::from(100)));
+
+    order.stop_price = None;
+    assert_eq!(high_water_mark(&order), None);
+  }
+
+  /// Check that orders are matched against cancellation filters
+  /// correctly.
+  #[test]
+  fn cancel_filter_matching() {
+    let order = sample_order();
+    let now = order.submitted_at.unwrap() + Duration::hours(3);
+    let mut cancel = CancelOrders {
+      ids: Vec::new(),
+      symbols: Vec::new(),
+      side: None,
+      type_: None,
+      older_than: None,
+    };
+    assert!(matches_cancel_filters(&order, &cancel, now));
+
+    cancel.symbols = vec!["SPY".to_string(), "AAPL".to_string()];
+    cancel.side = Some(Side::Sell);
+    cancel.type_ = Some(OrderType::TrailingStop);
+    cancel.older_than = Some(StdDuration::from_secs(2 * 60 * 60));
+    assert!(matches_cancel_filters(&order, &cancel, now));
+
+    cancel.older_than = Some(StdDuration::from_secs(4 * 60 * 60));
+    assert!(!matches_cancel_filters(&order, &cancel, now));
+
+    cancel.older_than = Some(StdDuration::MAX);
+    assert!(!matches_cancel_filters(&order, &cancel, now));
+
+    cancel.older_than = None;
+    cancel.type_ = Some(OrderType::Stop);
+    assert!(!matches_cancel_filters(&order, &cancel, now));
+
+    cancel.type_ = None;
+    cancel.side = Some(Side::Buy);
+    assert!(!matches_cancel_filters(&order, &cancel, now));
+
+    cancel.side = None;
+    cancel.symbols = vec!["SPY".to_string()];
+    assert!(!matches_cancel_filters(&order, &cancel, now));
+  }
+
+  /// Check that we can parse durations as accepted by
+  /// `--older-than`.
+  #[test]
+  fn duration_parsing() {
+    let older_than = |duration| {
+      let args = ["apcacli", "order", "cancel", "--older-than", duration];
+      match Args::try_parse_from(args).map(|args| args.command) {
+        Ok(Command::Order(Order::Cancel(cancel))) => Ok(cancel.older_than.unwrap()),
+        Ok(command) => panic!("unexpected command: {command:?}"),
+        Err(err) => Err(err.to_string()),
+      }
+    };
+
+    assert_eq!(older_than("90s").unwrap(), StdDuration::from_secs(90));
+    assert_eq!(older_than("90m").unwrap(), StdDuration::from_secs(90 * 60));
+    assert_eq!(
+      older_than("2w").unwrap(),
+      StdDuration::from_secs(14 * 24 * 60 * 60)
+    );
+
+    let err = older_than("12x").unwrap_err();
+    assert!(
+      err.contains("provided duration '12x' has invalid unit"),
+      "{err}"
+    );
+    let err = older_than("99999999999999999w").unwrap_err();
+    assert!(
+      err.contains("provided duration '99999999999999999w' is too large"),
+      "{err}"
+    );
+  }
+
+  /// Check that we derive the expected quantity from an amount to
+  /// risk.
+  #[test]
+  fn risk_sizing() {
+    assert_eq!("500".parse::<Risk>(), Ok(Risk::Value(Num::from(500))));
+    assert_eq!("1.5%".parse::<Risk>(), Ok(Risk::Percent(Num::new(15, 10))));
+    assert!("1.5x".parse::<Risk>().is_err());
+
+    let sizing = Sizing::new(
+      order::Side::Buy,
+      Num::from(500),
+      None,
+      Num::from(200),
+      Num::from(195),
+    )
+    .unwrap();
+    assert_eq!(sizing.distance, Num::from(5));
+    assert_eq!(sizing.quantity, Num::from(100));
+
+    // Quantities are rounded down, to not exceed the risk.
+    let sizing = Sizing::new(
+      order::Side::Sell,
+      Num::from(500),
+      None,
+      Num::from(100),
+      Num::from(103),
+    )
+    .unwrap();
+    assert_eq!(sizing.quantity, Num::from(166));
+
+    let err = Sizing::new(
+      order::Side::Buy,
+      Num::from(500),
+      None,
+      Num::from(200),
+      Num::from(205),
+    )
+    .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "stop price 205 has to be below entry price 200.00"
+    );
+
+    let err = Sizing::new(
+      order::Side::Buy,
+      Num::from(4),
+      None,
+      Num::from(200),
+      Num::from(195),
+    )
+    .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "risk of 4.00 does not cover a single share risking 5.00"
+    );
+  }
+
+  /// Check that we validate percentages of the account and convert
+  /// them into values correctly.
+  #[test]
+  fn percent_to_value_conversion() {
+    let () = validate_percent(&Num::new(1, 10)).unwrap();
+    let () = validate_percent(&Num::from(100)).unwrap();
+
+    for percent in [Num::from(0), Num::from(-5), Num::new(10001, 100)] {
+      let err = validate_percent(&percent).unwrap_err();
+      assert_eq!(
+        err.to_string(),
+        "percentage must be greater than 0 and at most 100"
+      );
+    }
+
+    let equity = Num::new(2500050, 100);
+    assert_eq!(
+      percent_of(equity.clone(), &Num::from(10)),
+      Num::new(250005, 100)
+    );
+    assert_eq!(percent_of(equity.clone(), &Num::from(100)), equity);
+    assert_eq!(
+      percent_of(Num::from(1000), &Num::new(25, 10)),
+      Num::from(25)
+    );
+  }
+
+  /// Check that `--stop-on-error` skips orders after a failed
+  /// submission, while all orders are submitted otherwise.
+  #[test]
+  fn batch_stop_on_error() {
+    let request = |id: &str| {
+      order::CreateReqInit {
+        client_order_id: Some(id.to_string()),
+        ..Default::default()
+      }
+      .init("SPY", order::Side::Buy, order::Amount::quantity(1))
+    };
+    let requests = || {
+      vec![
+        (2, request("ok-1")),
+        (3, request("fail")),
+        (4, request("ok-2")),
+        (5, request("ok-3")),
+      ]
+    };
+    let submit = |request: order::CreateReq| async move {
+      let id = request.client_order_id.unwrap();
+      ensure!(id != "fail", "rejected");
+      let mut order = sample_order();
+      order.client_order_id = id;
+      Ok(order)
+    };
+    let statuses = |results: Vec<BatchResult>| {
+      results
+        .into_iter()
+        .map(|result| match result {
+          BatchResult::Submitted { line, .. } => (line, "submitted"),
+          BatchResult::Failed { line, .. } => (line, "failed"),
+          BatchResult::Skipped { line } => (line, "skipped"),
+        })
+        .collect::<Vec<_>>()
+    };
+    let runtime = Builder::new_current_thread().build().unwrap();
+
+    let results = runtime.block_on(submit_batch(requests(), true, submit));
+    assert_eq!(
+      statuses(results),
+      vec![
+        (2, "submitted"),
+        (3, "failed"),
+        (4, "skipped"),
+        (5, "skipped")
+      ]
+    );
+
+    let results = runtime.block_on(submit_batch(requests(), false, submit));
+    assert_eq!(
+      statuses(results),
+      vec![
+        (2, "submitted"),
+        (3, "failed"),
+        (4, "submitted"),
+        (5, "submitted")
+      ]
+    );
+  }
+
+  /// Check that we can parse batch files in the supported formats.
+  #[test]
+  fn batch_order_parsing() {
+    let csv = "side,symbol,quantity,value,limit_price,time_in_force\n\
+               buy, SPY, 2,,400.5,today\n\
+               sell,AAPL,,1000,,\n\
+               hold,AAPL,1,,,\n\
+               buy,AAPL,1,1000,,\n";
+    let orders = parse_batch_orders(csv, BatchFormat::Csv).unwrap();
+    assert_eq!(orders.len(), 4);
+
+    let (line, order) = &orders[0];
+    let order = order.as_ref().unwrap();
+    assert_eq!(*line, 2);
+    assert_eq!(order.symbol, "SPY");
+    assert_eq!(order.quantity, Some(Num::from(2)));
+    assert_eq!(order.value, None);
+    assert_eq!(order.limit_price, Some(Num::new(4005, 10)));
+    assert_eq!(
+      order
+        .time_in_force
+        .as_ref()
+        .map(TimeInForce::to_time_in_force),
+      Some(order::TimeInForce::Day)
+    );
+
+    let (line, order) = &orders[1];
+    let order = order.as_ref().unwrap();
+    assert_eq!(*line, 3);
+    assert_eq!(order.quantity, None);
+    assert_eq!(order.value, Some(Num::from(1000)));
+    assert!(order.time_in_force.is_none());
+
+    assert_eq!(orders[2].0, 4);
+    assert!(orders[2].1.is_err());
+    assert_eq!(orders[3].0, 5);
+    let err = orders[3].1.as_ref().unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "exactly one of `quantity`, `value`, `percent_equity`, `percent_buying_power`, \
+       and `risk` needs to be provided"
+    );
+
+    let ndjson = r#"{"side": "buy", "symbol": "SPY", "quantity": 2, "trail_percent": "5", "client_order_id": "my-order"}
+
+{"side": "sell", "symbol": "SPY", "quantity": 2, "trail_percent": "5", "limit_price": 1}
+{"side": "sell", "symbol": "SPY", "quantity": 2, "foo": 1}
+"#;
+    let orders = parse_batch_orders(ndjson, BatchFormat::Ndjson).unwrap();
+    assert_eq!(orders.len(), 3);
+    assert_eq!(orders[0].0, 1);
+    let order = orders[0].1.as_ref().unwrap();
+    assert_eq!(order.trail_percent, Some(Num::from(5)));
+    assert_eq!(order.client_order_id.as_deref(), Some("my-order"));
+    assert_eq!(orders[1].0, 3);
+    assert!(orders[1].1.is_err());
+    assert_eq!(orders[2].0, 4);
+    assert!(orders[2].1.is_err());
+  }
+
+  /// Check that we infer the format of batch files correctly.
+  #[test]
+  fn batch_format_inference() {
+    let format = infer_batch_format(Path::new("orders.csv")).unwrap();
+    assert_eq!(format, BatchFormat::Csv);
+    let format = infer_batch_format(Path::new("orders.JSONL")).unwrap();
+    assert_eq!(format, BatchFormat::Ndjson);
+    assert!(infer_batch_format(Path::new("orders")).is_err());
+  }
+
+  /// Check that dry-run previews are converted into the documented
+  /// JSON schema.
+  #[test]
+  fn dry_run_json_schema() {
+    let request = order::CreateReqInit {
+      type_: order::Type::Limit,
+      limit_price: Some(Num::from(400)),
+      ..Default::default()
+    }
+    .init("SPY", order::Side::Buy, order::Amount::quantity(2));
+    let preview = DryRun::SubmitOrder { request: &request };
+    let json = to_json_value(&preview).unwrap();
+    assert_eq!(json["action"], "submit_order");
+    assert_eq!(json["request"]["symbol"], "SPY");
+    assert_eq!(json["request"]["qty"], "2");
+    assert_eq!(json["request"]["type"], "limit");
+    assert_eq!(json["request"]["limit_price"], "400");
+
+    let order = sample_order();
+    let preview = DryRun::CancelOrders {
+      orders: vec![&order],
+    };
+    let expected = json!({
+      "action": "cancel_orders",
+      "ids": ["904837e3-3b76-47ec-b432-046db621571b"],
+    });
+    assert_eq!(to_json_value(&preview).unwrap(), expected);
+
+    let preview = DryRun::ClosePosition {
+      symbol: "AAPL",
+      quantity: None,
+      percentage: None,
+    };
+    let expected = json!({
+      "action": "close_position",
+      "symbol": "AAPL",
+    });
+    assert_eq!(to_json_value(&preview).unwrap(), expected);
+
+    let preview = DryRun::ClosePositions {
+      symbols: vec!["AAPL", "SPY"],
+      orders: vec![&order],
+    };
+    let expected = json!({
+      "action": "close_positions",
+      "symbols": ["AAPL", "SPY"],
+      "cancel_ids": ["904837e3-3b76-47ec-b432-046db621571b"],
+    });
+    assert_eq!(to_json_value(&preview).unwrap(), expected);
+
+    let percentage = Num::from(50);
+    let preview = DryRun::ClosePosition {
+      symbol: "AAPL",
+      quantity: None,
+      percentage: Some(&percentage),
+    };
+    let expected = json!({
+      "action": "close_position",
+      "symbol": "AAPL",
+      "percentage": "50",
+    });
+    assert_eq!(to_json_value(&preview).unwrap(), expected);
+  }
+
+  /// Check that trade and non-trade account activities are converted
+  /// into the documented JSON schema.
+  #[test]
+  fn activity_json_schema() {
+    let trade = r#"{
+      "id": "20190524113406977::8efc7b9a-8b2b-4000-9955-d36e7db0df74",
+      "transaction_time": "2019-05-24T15:34:06.977Z",
+      "symbol": "AAPL",
+      "order_id": "904837e3-3b76-47ec-b432-046db621571b",
+      "side": "buy",
+      "qty": "10",
+      "cum_qty": "10",
+      "leaves_qty": "0",
+      "price": "181.09"
+    }"#;
+    let trade = from_json::<account_activities::TradeActivity>(trade).unwrap();
+    let trade = account_activities::Activity::Trade(trade);
+    let json = to_json_value(JsonActivity::from(&trade)).unwrap();
+    let expected = json!({
+      "kind": "trade",
+      "id": "20190524113406977::8efc7b9a-8b2b-4000-9955-d36e7db0df74",
+      "time": "2019-05-24T15:34:06.977Z",
+      "side": "buy",
+      "symbol": "AAPL",
+      "quantity": "10",
+      "price": "181.09",
+      "order_id": "904837e3-3b76-47ec-b432-046db621571b",
+    });
+    assert_eq!(json, expected);
+
+    let non_trade = r#"{
+      "id": "20190801011955195::5f596936-6f23-4cef-bdf1-3806aae57dbf",
+      "activity_type": "DIV",
+      "date": "2019-08-01",
+      "net_amount": "1.02",
+      "symbol": "T",
+      "qty": "2",
+      "per_share_amount": "0.51"
+    }"#;
+    let non_trade = from_json::<account_activities::NonTradeActivity>(non_trade).unwrap();
+    let non_trade = account_activities::Activity::NonTrade(non_trade);
+    let json = to_json_value(JsonActivity::from(&non_trade)).unwrap();
+    let expected = json!({
+      "kind": "non_trade",
+      "id": "20190801011955195::5f596936-6f23-4cef-bdf1-3806aae57dbf",
+      "date": "2019-08-01T00:00:00Z",
+      "type": "DIV",
+      "symbol": "T",
+      "amount": "1.02",
+    });
+    assert_eq!(json, expected);
+  }
+
+  /// Check that watch lists are converted into the documented JSON
+  /// schema.
+  #[test]
+  fn watchlist_json_schema() {
+    let watchlist = r#"{
+      "id": "3174d6df-7726-44b4-a5bd-7fda5ae6e009",
+      "account_id": "abe5b4d4-5a6e-4f4c-9cb3-1dbc3f43ee83",
+      "created_at": "2019-10-30T07:54:42.981322Z",
+      "updated_at": "2019-10-30T07:54:42.981322Z",
+      "name": "Monday List",
+      "assets": []
+    }"#;
+    let watchlist = from_json::<watchlist::Watchlist>(watchlist).unwrap();
+    let json = to_json_value(JsonWatchlist::from(&watchlist)).unwrap();
+    let expected = json!({
+      "id": "3174d6df-7726-44b4-a5bd-7fda5ae6e009",
+      "name": "Monday List",
+      "created_at": "2019-10-30T07:54:42.981322Z",
+      "updated_at": "2019-10-30T07:54:42.981322Z",
+      "symbols": [],
+    });
+    assert_eq!(json, expected);
+  }
+
+  /// Check that historical bars are converted into the documented JSON
+  /// schema.
+  #[test]
+  fn bar_json_schema() {
+    let bar = r#"{
+      "t": "2021-02-01T16:01:00Z",
+      "o": 133.32,
+      "h": 133.74,
+      "l": 133.31,
+      "c": 133.5,
+      "v": 9876,
+      "vw": 133.5
+    }"#;
+    let bar = from_json::<bars::Bar>(bar).unwrap();
+    let json = to_json_value(JsonBar::from(&bar)).unwrap();
+    let expected = json!({
+      "time": "2021-02-01T16:01:00Z",
+      "open": "133.32",
+      "high": "133.74",
+      "low": "133.31",
+      "close": "133.5",
+      "volume": 9876,
+      "vwap": "133.5",
+    });
+    assert_eq!(json, expected);
+  }
+
+  /// Check that bars are written out properly in the CSV and NDJSON
+  /// formats.
+  #[test]
+  fn bar_writing() {
+    let bar = r#"{
+      "t": "2021-02-01T16:01:00Z",
+      "o": 133.32,
+      "h": 133.74,
+      "l": 133.31,
+      "c": 133.5,
+      "v": 9876,
+      "vw": 133.51
+    }"#;
+    let bar = from_json::<bars::Bar>(bar).unwrap();
+
+    let mut csv = Vec::new();
+    let () = write_bars_header(&mut csv, BarsFormat::Csv).unwrap();
+    let () = write_bar(&mut csv, BarsFormat::Csv, &bar).unwrap();
+    let () = write_bar(&mut csv, BarsFormat::Csv, &bar).unwrap();
+    let expected = "\
+time,open,high,low,close,volume,vwap
+2021-02-01T16:01:00+00:00,133.32,133.74,133.31,133.5,9876,133.51
+2021-02-01T16:01:00+00:00,133.32,133.74,133.31,133.5,9876,133.51
+";
+    assert_eq!(String::from_utf8(csv).unwrap(), expected);
+
+    let mut ndjson = Vec::new();
+    let () = write_bars_header(&mut ndjson, BarsFormat::Ndjson).unwrap();
+    let () = write_bar(&mut ndjson, BarsFormat::Ndjson, &bar).unwrap();
+    let () = write_bar(&mut ndjson, BarsFormat::Ndjson, &bar).unwrap();
+    let ndjson = String::from_utf8(ndjson).unwrap();
+    let lines = ndjson.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    for line in lines {
+      let json = from_json::<serde_json::Value>(line).unwrap();
+      assert_eq!(json["close"], "133.5");
+      assert_eq!(json["vwap"], "133.51");
+    }
+  }
+
+  /// Check that points in time are attributed to the correct trading
+  /// day.
+  #[test]
+  fn trading_day_determination() {
+    let day = |time: &str| trading_day(&DateTime::parse_from_rfc3339(time).unwrap().into());
+    let date = NaiveDate::from_ymd_opt(2026, 10, 16).unwrap();
+    assert_eq!(day("2026-10-16T13:30:00Z"), date);
+    // Extended hours trading continues past midnight UTC.
+    assert_eq!(day("2026-10-16T23:59:00Z"), date);
+    assert_eq!(day("2026-10-17T03:59:59Z"), date);
+    assert_eq!(day("2026-10-17T04:00:00Z"), date.succ_opt().unwrap());
+  }
+
   /// Make sure that we do not fail extension discovery when no
   /// extensions can be found.
   #[test]
@@ -0,0 +1,276 @@
+// Copyright (C) 2026 Daniel Mueller <deso@posteo.net>
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Computation of the trades necessary for rebalancing a portfolio
+//! towards target weights.
+
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use apca::api::v2::order;
+
+use anyhow::ensure;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use num_decimal::Num;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::args::Rounding;
+
+
+/// The number of decimal places Alpaca supports for fractional
+/// quantities.
+const FRACTIONAL_DIGITS: usize = 9;
+
+
+/// Target weights, in percent of account equity, keyed by symbol.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Targets(pub BTreeMap<String, Num>);
+
+impl Targets {
+  /// Parse targets from a TOML string.
+  fn from_toml(toml: &str) -> Result<Self> {
+    let targets = toml::from_str::<Self>(toml)?;
+    let mut total = Num::from(0);
+    for (symbol, weight) in &targets.0 {
+      ensure!(
+        !weight.is_negative(),
+        "target weight of {symbol} must not be negative"
+      );
+      total += weight;
+    }
+    ensure!(
+      total <= Num::from(100),
+      "target weights add up to {total}%, which exceeds 100%"
+    );
+    Ok(targets)
+  }
+
+  /// Load targets from the file at the given path.
+  pub fn load(path: &Path) -> Result<Self> {
+    let toml = read_to_string(path)
+      .with_context(|| format!("failed to read targets file {}", path.display()))?;
+    Self::from_toml(&toml)
+      .with_context(|| format!("failed to parse targets file {}", path.display()))
+  }
+}
+
+
+/// The current holding in a symbol, as relevant for rebalancing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Holding {
+  /// The quantity held (zero if there is no position).
+  pub quantity: Num,
+  /// The price at which to value the holding and trades.
+  pub price: Num,
+  /// Whether the asset can be traded in fractional quantities.
+  pub fractionable: bool,
+}
+
+
+/// The adjustment to the holding in a single symbol.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Adjustment {
+  /// The symbol of the asset.
+  pub symbol: String,
+  /// The current weight of the holding, in percent of equity.
+  pub current_weight: Num,
+  /// The target weight of the holding, in percent of equity.
+  pub target_weight: Num,
+  /// The side of the trade to make, if any.
+  pub side: Option<order::Side>,
+  /// The quantity to trade (zero if no trade is necessary).
+  pub quantity: Num,
+  /// The estimated value of the trade.
+  pub notional: Num,
+  /// The ID of the order submitted for the trade, if any.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub order_id: Option<order::Id>,
+}
+
+
+/// Round a quantity towards zero, to the precision requested.
+pub fn round_quantity(quantity: &Num, rounding: Rounding, fractionable: bool) -> Num {
+  match rounding {
+    Rounding::Fractional if fractionable => {
+      let scale = Num::from(10u64.pow(FRACTIONAL_DIGITS as u32));
+      (quantity * &scale).trunc() / scale
+    },
+    Rounding::Fractional | Rounding::Whole => quantity.trunc(),
+  }
+}
+
+
+/// Compute the adjustments necessary to bring the holdings in all
+/// target symbols to their target weights.
+///
+/// Holdings whose weight is within `tolerance` percentage points of
+/// the target are left alone. Quantities are rounded towards zero, so
+/// that we never buy more than we can afford or sell more than we
+/// hold.
+pub fn plan(
+  equity: &Num,
+  targets: &Targets,
+  holdings: &BTreeMap<String, Holding>,
+  tolerance: &Num,
+  rounding: Rounding,
+) -> Result<Vec<Adjustment>> {
+  ensure!(equity.is_positive(), "account equity is not positive");
+
+  targets
+    .0
+    .iter()
+    .map(|(symbol, target_weight)| {
+      let holding = holdings
+        .get(symbol)
+        .with_context(|| format!("no holding information for {symbol}"))?;
+      let current_value = &holding.quantity * &holding.price;
+      let current_weight = &current_value / equity * 100;
+
+      let within_tolerance =
+        current_weight >= target_weight - tolerance && current_weight <= target_weight + tolerance;
+      let (side, quantity) = if within_tolerance {
+        (None, Num::from(0))
+      } else {
+        let target_value = equity * target_weight / 100;
+        let (side, value) = if target_value > current_value {
+          (order::Side::Buy, target_value - &current_value)
+        } else {
+          (order::Side::Sell, current_value - &target_value)
+        };
+        let quantity = round_quantity(&(value / &holding.price), rounding, holding.fractionable);
+        if quantity.is_zero() {
+          (None, quantity)
+        } else {
+          (Some(side), quantity)
+        }
+      };
+
+      let adjustment = Adjustment {
+        symbol: symbol.clone(),
+        current_weight,
+        target_weight: target_weight.clone(),
+        side,
+        notional: &quantity * &holding.price,
+        quantity,
+        order_id: None,
+      };
+      Ok(adjustment)
+    })
+    .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we can parse targets and reject invalid ones.
+  #[test]
+  fn parse_targets() {
+    let toml = r#"
+      SPY = 60
+      AGG = "30.5"
+      GLD = 9.5
+    "#;
+    let targets = Targets::from_toml(toml).unwrap();
+    assert_eq!(targets.0["SPY"], Num::from(60));
+    assert_eq!(targets.0["AGG"], Num::new(305, 10));
+    assert_eq!(targets.0["GLD"], Num::new(95, 10));
+
+    let toml = r#"
+      SPY = 60
+      AGG = 41
+    "#;
+    let err = Targets::from_toml(toml).unwrap_err();
+    assert!(err.to_string().contains("exceeds 100%"), "{err}");
+
+    let toml = r#"SPY = -1"#;
+    let err = Targets::from_toml(toml).unwrap_err();
+    assert!(err.to_string().contains("must not be negative"), "{err}");
+  }
+
+  /// Check that we compute the expected rebalancing plan.
+  #[test]
+  fn rebalance_plan() {
+    let targets = Targets::from_toml("SPY = 50\nAGG = 40\nGLD = 10\nXLK = 0").unwrap();
+    let holdings = [
+      ("SPY", 14, 500, false),
+      ("AGG", 30, 100, false),
+      ("GLD", 0, 300, true),
+      ("XLK", 5, 100, false),
+    ]
+    .into_iter()
+    .map(|(symbol, quantity, price, fractionable)| {
+      let holding = Holding {
+        quantity: Num::from(quantity),
+        price: Num::from(price),
+        fractionable,
+      };
+      (symbol.to_string(), holding)
+    })
+    .collect::<BTreeMap<_, _>>();
+    let equity = Num::from(20000);
+    let tolerance = Num::from(1);
+
+    let plan = plan(
+      &equity,
+      &targets,
+      &holdings,
+      &tolerance,
+      Rounding::Fractional,
+    )
+    .unwrap();
+    let plan = plan
+      .into_iter()
+      .map(|adjustment| (adjustment.symbol.clone(), adjustment))
+      .collect::<BTreeMap<_, _>>();
+
+    // SPY is at 35% and has to go up to 50%, i.e., 3000 USD worth or
+    // six shares.
+    let spy = &plan["SPY"];
+    assert_eq!(spy.current_weight, Num::from(35));
+    assert_eq!(spy.side, Some(order::Side::Buy));
+    assert_eq!(spy.quantity, Num::from(6));
+    assert_eq!(spy.notional, Num::from(3000));
+
+    // AGG is at 15% and has to go up to 40%, i.e., 5000 USD worth.
+    let agg = &plan["AGG"];
+    assert_eq!(agg.side, Some(order::Side::Buy));
+    assert_eq!(agg.quantity, Num::from(50));
+
+    // GLD is fractionable, so we buy a fractional quantity.
+    let gld = &plan["GLD"];
+    assert_eq!(gld.side, Some(order::Side::Buy));
+    assert_eq!(gld.quantity, Num::new(6666666666u64, 1000000000u64));
+
+    // XLK is to be liquidated entirely.
+    let xlk = &plan["XLK"];
+    assert_eq!(xlk.current_weight, Num::new(5, 2));
+    assert_eq!(xlk.side, Some(order::Side::Sell));
+    assert_eq!(xlk.quantity, Num::from(5));
+
+    let plan = super::plan(&equity, &targets, &holdings, &tolerance, Rounding::Whole).unwrap();
+    let gld = plan
+      .iter()
+      .find(|adjustment| adjustment.symbol == "GLD")
+      .unwrap();
+    assert_eq!(gld.quantity, Num::from(6));
+
+    // With a larger tolerance band, XLK is left alone.
+    let tolerance = Num::from(3);
+    let plan = super::plan(&equity, &targets, &holdings, &tolerance, Rounding::Whole).unwrap();
+    let xlk = plan
+      .iter()
+      .find(|adjustment| adjustment.symbol == "XLK")
+      .unwrap();
+    assert_eq!(xlk.side, None);
+    assert_eq!(xlk.quantity, Num::from(0));
+  }
+}